@@ -0,0 +1,435 @@
+use global;
+use var;
+
+use std::{error,fmt};
+
+#[derive(Debug)]
+pub enum ArithError {
+	Syntax(String),
+	DivisionByZero,
+	NegativeExponent,
+	Unbound(Vec<u8>),
+	TooDeep,
+}
+impl fmt::Display for ArithError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ArithError::Syntax(ref s) => write!(f, "syntax error in expression: {}", s),
+			ArithError::DivisionByZero => write!(f, "division by 0"),
+			ArithError::NegativeExponent => write!(f, "exponent less than 0"),
+			ArithError::Unbound(ref s) => write!(f, "{}: unbound variable", String::from_utf8_lossy(s)),
+			ArithError::TooDeep => write!(f, "expression recursion level exceeded"),
+		}
+	}
+}
+impl error::Error for ArithError {
+	fn description(&self) -> &str {
+		match *self {
+			ArithError::Syntax(..) => "syntax error in expression",
+			ArithError::DivisionByZero => "division by 0",
+			ArithError::NegativeExponent => "exponent less than 0",
+			ArithError::Unbound(..) => "unbound variable",
+			ArithError::TooDeep => "expression recursion level exceeded",
+		}
+	}
+}
+
+pub type ArithResult<T> = Result<T, ArithError>;
+
+/// Variables may hold expressions themselves, which are evaluated up to this depth.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum BinOp {
+	Mul, Div, Rem, Add, Sub, Shl, Shr, Lt, Le, Gt, Ge, Eq, Ne, BitAnd, BitXor, BitOr, And, Or, Pow, Comma,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum UnOp { Neg, Plus, Not, BitNot }
+
+#[derive(Debug)]
+enum Expr {
+	Num(i64),
+	Var(Vec<u8>),
+	Unary(UnOp, Box<Expr>),
+	/// `++x` and `--x` are stored with `is_prefix` set, and yield the updated value.
+	Incr { name: Vec<u8>, delta: i64, is_prefix: bool },
+	Binary(BinOp, Box<Expr>, Box<Expr>),
+	Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+	Assign(Vec<u8>, Option<BinOp>, Box<Expr>),
+}
+
+fn binary_op(s: &[u8]) -> Option<(BinOp, u8)> {
+	let op = match s {
+		b"**" => BinOp::Pow,
+		b"*" => BinOp::Mul,
+		b"/" => BinOp::Div,
+		b"%" => BinOp::Rem,
+		b"+" => BinOp::Add,
+		b"-" => BinOp::Sub,
+		b"<<" => BinOp::Shl,
+		b">>" => BinOp::Shr,
+		b"<" => BinOp::Lt,
+		b"<=" => BinOp::Le,
+		b">" => BinOp::Gt,
+		b">=" => BinOp::Ge,
+		b"==" => BinOp::Eq,
+		b"!=" => BinOp::Ne,
+		b"&" => BinOp::BitAnd,
+		b"^" => BinOp::BitXor,
+		b"|" => BinOp::BitOr,
+		b"&&" => BinOp::And,
+		b"||" => BinOp::Or,
+		_ => { return None; },
+	};
+	// Binding power, higher binds tighter.
+	let prec = match op {
+		BinOp::Comma => 0,
+		BinOp::Or => 3,
+		BinOp::And => 4,
+		BinOp::BitOr => 5,
+		BinOp::BitXor => 6,
+		BinOp::BitAnd => 7,
+		BinOp::Eq | BinOp::Ne => 8,
+		BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 9,
+		BinOp::Shl | BinOp::Shr => 10,
+		BinOp::Add | BinOp::Sub => 11,
+		BinOp::Mul | BinOp::Div | BinOp::Rem => 12,
+		BinOp::Pow => 13,
+	};
+	Some((op, prec))
+}
+
+struct Parser<'a> {
+	expr: &'a [u8],
+	i: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn skip_whitespaces(&mut self) {
+		while self.expr.get(self.i).map_or(false, |&c| c == b' ' || c == b'\t' || c == b'\n') {
+			self.i += 1;
+		}
+	}
+
+	fn starts_with(&mut self, s: &[u8]) -> bool {
+		self.skip_whitespaces();
+		self.expr[self.i ..].starts_with(s)
+	}
+
+	fn error<T>(&self) -> ArithResult<T> {
+		Err(ArithError::Syntax(match self.expr.get(self.i) {
+			Some(_) => format!("error token is \"{}\"", String::from_utf8_lossy(&self.expr[self.i ..])),
+			None => "operand expected".to_string(),
+		}))
+	}
+
+	/// Reads the longest operator made of punctuation at the current position.
+	fn peek_operator(&mut self) -> &'a [u8] {
+		self.skip_whitespaces();
+		let rest = &self.expr[self.i ..];
+		for &len in &[3, 2, 1] {
+			if rest.len() >= len {
+				let op = &rest[.. len];
+				let known = match op {
+					b"<<=" | b">>=" | b"**=" => true,
+					b"**" | b"<<" | b">>" | b"<=" | b">=" | b"==" | b"!=" | b"&&" | b"||" | b"++" | b"--" => true,
+					b"*=" | b"/=" | b"%=" | b"+=" | b"-=" | b"&=" | b"^=" | b"|=" => true,
+					b"*" | b"/" | b"%" | b"+" | b"-" | b"<" | b">" | b"&" | b"^" | b"|" | b"=" | b"!" | b"~" => true,
+					b"?" | b":" | b"," | b"(" | b")" => true,
+					_ => false,
+				};
+				if known {
+					return op;
+				}
+			}
+		}
+		&[]
+	}
+
+	fn read_name(&mut self) -> Option<Vec<u8>> {
+		self.skip_whitespaces();
+		let orig = self.i;
+		if self.expr.get(self.i).map_or(false, |&c| c == b'_' || (c as char).is_ascii_alphabetic()) {
+			while self.expr.get(self.i).map_or(false, |&c| c == b'_' || (c as char).is_ascii_alphanumeric()) {
+				self.i += 1;
+			}
+		}
+		if orig == self.i { None } else { Some(self.expr[orig .. self.i].to_vec()) }
+	}
+
+	fn read_number(&mut self) -> ArithResult<i64> {
+		let orig = self.i;
+		while self.expr.get(self.i).map_or(false, |&c| c == b'#' || (c as char).is_ascii_alphanumeric()) {
+			self.i += 1;
+		}
+		parse_number(&self.expr[orig .. self.i]).ok_or_else(|| ArithError::Syntax(
+			format!("invalid number: {}", String::from_utf8_lossy(&self.expr[orig .. self.i]))))
+	}
+
+	fn parse_primary(&mut self) -> ArithResult<Expr> {
+		self.skip_whitespaces();
+		match self.expr.get(self.i) {
+			Some(&b'(') => {
+				self.i += 1;
+				let e = self.parse_comma()?;
+				if !self.starts_with(b")") {
+					return self.error();
+				}
+				self.i += 1;
+				Ok(e)
+			},
+			Some(&c) if (c as char).is_ascii_digit() => Ok(Expr::Num(self.read_number()?)),
+			_ => match self.read_name() {
+				Some(name) => if self.starts_with(b"++") || self.starts_with(b"--") {
+					let delta = if self.expr[self.i] == b'+' { 1 } else { -1 };
+					self.i += 2;
+					Ok(Expr::Incr { name: name, delta: delta, is_prefix: false })
+				} else {
+					Ok(Expr::Var(name))
+				},
+				None => self.error(),
+			},
+		}
+	}
+
+	fn parse_unary(&mut self) -> ArithResult<Expr> {
+		let op = self.peek_operator();
+		let unop = match op {
+			b"++" | b"--" => {
+				self.i += 2;
+				return match self.read_name() {
+					Some(name) => Ok(Expr::Incr { name: name, delta: if op == b"++" { 1 } else { -1 }, is_prefix: true }),
+					None => self.error(),
+				};
+			},
+			b"-" => UnOp::Neg,
+			b"+" => UnOp::Plus,
+			b"!" => UnOp::Not,
+			b"~" => UnOp::BitNot,
+			_ => { return self.parse_primary(); },
+		};
+		self.i += 1;
+		Ok(Expr::Unary(unop, Box::new(self.parse_unary()?)))
+	}
+
+	/// Precedence climbing over the binary operators from `||` to `**`.
+	fn parse_binary(&mut self, min_prec: u8) -> ArithResult<Expr> {
+		let mut lhs = self.parse_unary()?;
+		loop {
+			let op = self.peek_operator();
+			let (binop, prec) = match binary_op(op) {
+				Some((binop, prec)) if prec >= min_prec => (binop, prec),
+				_ => { break; },
+			};
+			self.i += op.len();
+			// `**` is right associative.
+			let rhs = self.parse_binary(if binop == BinOp::Pow { prec } else { prec + 1 })?;
+			lhs = Expr::Binary(binop, Box::new(lhs), Box::new(rhs));
+		}
+		Ok(lhs)
+	}
+
+	fn parse_conditional(&mut self) -> ArithResult<Expr> {
+		let cond = self.parse_binary(3)?;
+		if !self.starts_with(b"?") {
+			return Ok(cond);
+		}
+		self.i += 1;
+		let then = self.parse_comma()?;
+		if !self.starts_with(b":") {
+			return self.error();
+		}
+		self.i += 1;
+		let otherwise = self.parse_assignment()?;
+		Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)))
+	}
+
+	fn parse_assignment(&mut self) -> ArithResult<Expr> {
+		let orig = self.i;
+		if let Some(name) = self.read_name() {
+			let op = self.peek_operator();
+			let assign_op = match op {
+				b"=" => Some(None),
+				_ if op.len() >= 2 && op.last() == Some(&b'=') && op != b"==" && op != b"!=" && op != b"<=" && op != b">=" =>
+					binary_op(&op[.. op.len() - 1]).map(|(binop, _)| Some(binop)),
+				_ => None,
+			};
+			if let Some(binop) = assign_op {
+				self.i += op.len();
+				let rhs = self.parse_assignment()?;
+				return Ok(Expr::Assign(name, binop, Box::new(rhs)));
+			}
+		}
+		self.i = orig;
+		self.parse_conditional()
+	}
+
+	fn parse_comma(&mut self) -> ArithResult<Expr> {
+		let mut lhs = self.parse_assignment()?;
+		while self.starts_with(b",") {
+			self.i += 1;
+			let rhs = self.parse_assignment()?;
+			lhs = Expr::Binary(BinOp::Comma, Box::new(lhs), Box::new(rhs));
+		}
+		Ok(lhs)
+	}
+}
+
+/// Parses decimal, `0x` hexadecimal, `0` octal and `base#digits` literals.
+fn parse_number(s: &[u8]) -> Option<i64> {
+	let s = ::std::str::from_utf8(s).ok()?;
+	let (radix, digits) = if let Some(n) = s.find('#') {
+		let radix: u32 = s[.. n].parse().ok()?;
+		if radix < 2 || radix > 36 {
+			return None;
+		}
+		(radix, &s[n + 1 ..])
+	} else if s.starts_with("0x") || s.starts_with("0X") {
+		(16, &s[2 ..])
+	} else if s.len() > 1 && s.starts_with('0') {
+		(8, &s[1 ..])
+	} else {
+		(10, s)
+	};
+	if digits.is_empty() {
+		return None;
+	}
+	let mut n: i64 = 0;
+	for c in digits.chars() {
+		n = n.wrapping_mul(radix as i64).wrapping_add(c.to_digit(radix)? as i64);
+	}
+	Some(n)
+}
+
+struct Evaluator<'a> {
+	state: &'a mut global::State,
+	depth: usize,
+}
+
+impl<'a> Evaluator<'a> {
+	fn value_of(&mut self, name: &[u8]) -> ArithResult<i64> {
+		let value = match self.state.vars.get(name) {
+			Some(&var::Value::Scalar(ref s)) => s.clone(),
+			Some(&var::Value::Array(ref a)) => a.get(0).cloned().unwrap_or(vec![]),
+			None => if self.state.options.nounset {
+				return Err(ArithError::Unbound(name.to_vec()));
+			} else {
+				vec![]
+			},
+		};
+		if value.iter().all(|&c| c == b' ' || c == b'\t' || c == b'\n') {
+			return Ok(0);
+		}
+		if self.depth >= MAX_DEPTH {
+			return Err(ArithError::TooDeep);
+		}
+		self.depth += 1;
+		let r = self.eval_str(&value);
+		self.depth -= 1;
+		r
+	}
+
+	fn assign(&mut self, name: &[u8], n: i64) -> i64 {
+		self.state.vars.set_scalar(name, n.to_string().as_bytes());
+		n
+	}
+
+	fn binary(&mut self, op: BinOp, l: i64, r: i64) -> ArithResult<i64> {
+		Ok(match op {
+			BinOp::Mul => l.wrapping_mul(r),
+			BinOp::Div | BinOp::Rem if r == 0 => { return Err(ArithError::DivisionByZero); },
+			BinOp::Div => l.wrapping_div(r),
+			BinOp::Rem => l.wrapping_rem(r),
+			BinOp::Add => l.wrapping_add(r),
+			BinOp::Sub => l.wrapping_sub(r),
+			BinOp::Shl => l.wrapping_shl(r as u32),
+			BinOp::Shr => l.wrapping_shr(r as u32),
+			BinOp::Lt => (l < r) as i64,
+			BinOp::Le => (l <= r) as i64,
+			BinOp::Gt => (l > r) as i64,
+			BinOp::Ge => (l >= r) as i64,
+			BinOp::Eq => (l == r) as i64,
+			BinOp::Ne => (l != r) as i64,
+			BinOp::BitAnd => l & r,
+			BinOp::BitXor => l ^ r,
+			BinOp::BitOr => l | r,
+			BinOp::Pow if r < 0 => { return Err(ArithError::NegativeExponent); },
+			BinOp::Pow => {
+				let (mut n, mut base, mut exp): (i64, i64, u64) = (1, l, r as u64);
+				while exp > 0 {
+					if exp & 1 == 1 {
+						n = n.wrapping_mul(base);
+					}
+					base = base.wrapping_mul(base);
+					exp >>= 1;
+				}
+				n
+			},
+			BinOp::And => (l != 0 && r != 0) as i64,
+			BinOp::Or => (l != 0 || r != 0) as i64,
+			BinOp::Comma => r,
+		})
+	}
+
+	fn eval(&mut self, e: &Expr) -> ArithResult<i64> {
+		Ok(match *e {
+			Expr::Num(n) => n,
+			Expr::Var(ref name) => self.value_of(name)?,
+			Expr::Unary(op, ref e) => {
+				let n = self.eval(e)?;
+				match op {
+					UnOp::Neg => n.wrapping_neg(),
+					UnOp::Plus => n,
+					UnOp::Not => (n == 0) as i64,
+					UnOp::BitNot => !n,
+				}
+			},
+			Expr::Incr { ref name, delta, is_prefix } => {
+				let n = self.value_of(name)?;
+				self.assign(name, n.wrapping_add(delta));
+				if is_prefix { n.wrapping_add(delta) } else { n }
+			},
+			// Only the operands that are needed are evaluated, so that their assignments take no effect otherwise.
+			Expr::Binary(BinOp::And, ref l, ref r) => (self.eval(l)? != 0 && self.eval(r)? != 0) as i64,
+			Expr::Binary(BinOp::Or, ref l, ref r) => (self.eval(l)? != 0 || self.eval(r)? != 0) as i64,
+			Expr::Binary(op, ref l, ref r) => {
+				let l = self.eval(l)?;
+				let r = self.eval(r)?;
+				self.binary(op, l, r)?
+			},
+			Expr::Cond(ref cond, ref then, ref otherwise) => if self.eval(cond)? != 0 {
+				self.eval(then)?
+			} else {
+				self.eval(otherwise)?
+			},
+			Expr::Assign(ref name, op, ref e) => {
+				let r = self.eval(e)?;
+				let n = match op {
+					Some(op) => {
+						let l = self.value_of(name)?;
+						self.binary(op, l, r)?
+					},
+					None => r,
+				};
+				self.assign(name, n)
+			},
+		})
+	}
+
+	fn eval_str(&mut self, expr: &[u8]) -> ArithResult<i64> {
+		let mut parser = Parser { expr: expr, i: 0 };
+		let e = parser.parse_comma()?;
+		parser.skip_whitespaces();
+		if parser.i != expr.len() {
+			return parser.error();
+		}
+		self.eval(&e)
+	}
+}
+
+/// Evaluates an arithmetic expression whose parameters have already been expanded.
+pub fn eval(state: &mut global::State, expr: &[u8]) -> ArithResult<i64> {
+	let mut evaluator = Evaluator { state: state, depth: 0 };
+	evaluator.eval_str(expr)
+}
@@ -0,0 +1,295 @@
+//! An owned copy of the syntax tree of `parser`, which does not borrow the source.
+//! It can be kept after the source is gone, and with the `serialize` feature it can be serialised with serde.
+//! `Shell::eval_ast` runs it by viewing it as a `parser` tree that borrows from it.
+
+use parser;
+pub use parser::{RedirectType,Connector};
+
+use std::fmt;
+#[cfg(feature = "serialize")]
+use serde;
+
+/// A word as written in the source, before expansion.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Word(pub Vec<u8>);
+
+impl<'a> From<&'a [u8]> for Word {
+	fn from(s: &'a [u8]) -> Word {
+		Word(s.to_vec())
+	}
+}
+
+impl fmt::Display for Word {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", String::from_utf8_lossy(&self.0))
+	}
+}
+
+/// Words are serialised as their bytes, which JSON writes as an array of numbers, because a word
+/// does not have to be UTF-8.
+#[cfg(feature = "serialize")]
+impl serde::Serialize for Word {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_bytes(&self.0)
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Redirect {
+	pub target: Word,
+	pub from: i32,
+	pub typ: RedirectType,
+}
+
+/// A simple command. `name` is `None` when the command consists only of assignments and redirects.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct SimpleCommand {
+	pub assignments: Vec<Word>,
+	pub name: Option<Word>,
+	pub arguments: Vec<Word>,
+	pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum CompoundCommand {
+	BraceGroup(List),
+	If { conditions: Vec<(List, List)>, otherwise: Option<List> },
+	While { is_until: bool, condition: List, body: List },
+	Arithmetic(Word),
+	Conditional(CondExpr),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum CondExpr {
+	Word(Word),
+	Unary(Word, Word),
+	Binary(Word, Word, Word),
+	Not(Box<CondExpr>),
+	And(Box<CondExpr>, Box<CondExpr>),
+	Or(Box<CondExpr>, Box<CondExpr>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum Command {
+	Simple(SimpleCommand),
+	Compound(CompoundCommand, Vec<Redirect>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Pipeline {
+	pub commands: Vec<Command>,
+	pub is_negated: bool,
+	pub text: Word,
+	pub command_texts: Vec<Word>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct AndOr {
+	pub first: Pipeline,
+	pub rest: Vec<(Connector, Pipeline)>,
+	pub is_background: bool,
+	pub text: Word,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct List {
+	pub items: Vec<AndOr>,
+}
+
+/// Parses a whole script into an owned tree.
+pub fn parse(source: &[u8]) -> parser::ParseResult<List> {
+	parser::parse(source).map(|list| List::from(&list))
+}
+
+fn words(words: &[&[u8]]) -> Vec<Word> {
+	words.iter().map(|&w| Word::from(w)).collect()
+}
+
+fn redirects(redirects: &[parser::Redirect]) -> Vec<Redirect> {
+	redirects.iter().map(Redirect::from).collect()
+}
+
+impl<'a, 'b> From<&'b parser::Redirect<'a>> for Redirect {
+	fn from(r: &'b parser::Redirect<'a>) -> Redirect {
+		Redirect { target: Word::from(r.target), from: r.from, typ: r.typ }
+	}
+}
+
+impl<'a, 'b> From<&'b parser::SimpleCommand<'a>> for SimpleCommand {
+	fn from(c: &'b parser::SimpleCommand<'a>) -> SimpleCommand {
+		SimpleCommand {
+			assignments: words(&c.assignments),
+			name: if c.name.is_empty() { None } else { Some(Word::from(c.name)) },
+			arguments: words(&c.arguments),
+			redirects: redirects(&c.redirects),
+		}
+	}
+}
+
+impl<'a, 'b> From<&'b parser::CompoundCommand<'a>> for CompoundCommand {
+	fn from(c: &'b parser::CompoundCommand<'a>) -> CompoundCommand {
+		match *c {
+			parser::CompoundCommand::BraceGroup(ref list) => CompoundCommand::BraceGroup(List::from(list)),
+			parser::CompoundCommand::If { ref conditions, ref otherwise } => CompoundCommand::If {
+				conditions: conditions.iter().map(|&(ref c, ref b)| (List::from(c), List::from(b))).collect(),
+				otherwise: otherwise.as_ref().map(List::from),
+			},
+			parser::CompoundCommand::While { is_until, ref condition, ref body } =>
+				CompoundCommand::While { is_until: is_until, condition: List::from(condition), body: List::from(body) },
+			parser::CompoundCommand::Arithmetic(expr) => CompoundCommand::Arithmetic(Word::from(expr)),
+			parser::CompoundCommand::Conditional(ref expr) => CompoundCommand::Conditional(CondExpr::from(expr)),
+		}
+	}
+}
+
+impl<'a, 'b> From<&'b parser::CondExpr<'a>> for CondExpr {
+	fn from(e: &'b parser::CondExpr<'a>) -> CondExpr {
+		let boxed = |e: &parser::CondExpr| Box::new(CondExpr::from(e));
+		match *e {
+			parser::CondExpr::Word(w) => CondExpr::Word(Word::from(w)),
+			parser::CondExpr::Unary(op, w) => CondExpr::Unary(Word::from(op), Word::from(w)),
+			parser::CondExpr::Binary(l, op, r) => CondExpr::Binary(Word::from(l), Word::from(op), Word::from(r)),
+			parser::CondExpr::Not(ref e) => CondExpr::Not(boxed(e)),
+			parser::CondExpr::And(ref l, ref r) => CondExpr::And(boxed(l), boxed(r)),
+			parser::CondExpr::Or(ref l, ref r) => CondExpr::Or(boxed(l), boxed(r)),
+		}
+	}
+}
+
+impl<'a, 'b> From<&'b parser::Command<'a>> for Command {
+	fn from(c: &'b parser::Command<'a>) -> Command {
+		match *c {
+			parser::Command::Simple(ref c) => Command::Simple(SimpleCommand::from(c)),
+			parser::Command::Compound(ref c, ref r) => Command::Compound(CompoundCommand::from(c), redirects(r)),
+		}
+	}
+}
+
+impl<'a, 'b> From<&'b parser::Pipeline<'a>> for Pipeline {
+	fn from(p: &'b parser::Pipeline<'a>) -> Pipeline {
+		Pipeline {
+			commands: p.commands.iter().map(Command::from).collect(),
+			is_negated: p.is_negated,
+			text: Word::from(p.text),
+			command_texts: words(&p.command_texts),
+		}
+	}
+}
+
+impl<'a, 'b> From<&'b parser::AndOr<'a>> for AndOr {
+	fn from(a: &'b parser::AndOr<'a>) -> AndOr {
+		AndOr {
+			first: Pipeline::from(&a.first),
+			rest: a.rest.iter().map(|&(c, ref p)| (c, Pipeline::from(p))).collect(),
+			is_background: a.is_background,
+			text: Word::from(a.text),
+		}
+	}
+}
+
+impl<'a, 'b> From<&'b parser::List<'a>> for List {
+	fn from(l: &'b parser::List<'a>) -> List {
+		List { items: l.items.iter().map(AndOr::from).collect() }
+	}
+}
+
+fn word_slices(words: &[Word]) -> Vec<&[u8]> {
+	words.iter().map(|w| &w.0[..]).collect()
+}
+
+fn parser_redirects(redirects: &[Redirect]) -> Vec<parser::Redirect> {
+	redirects.iter().map(parser::Redirect::from).collect()
+}
+
+impl<'a> From<&'a Redirect> for parser::Redirect<'a> {
+	fn from(r: &'a Redirect) -> parser::Redirect<'a> {
+		parser::Redirect { target: &r.target.0, from: r.from, typ: r.typ }
+	}
+}
+
+impl<'a> From<&'a SimpleCommand> for parser::SimpleCommand<'a> {
+	fn from(c: &'a SimpleCommand) -> parser::SimpleCommand<'a> {
+		parser::SimpleCommand {
+			assignments: word_slices(&c.assignments),
+			name: c.name.as_ref().map_or(&[][..], |w| &w.0[..]),
+			arguments: word_slices(&c.arguments),
+			redirects: parser_redirects(&c.redirects),
+		}
+	}
+}
+
+impl<'a> From<&'a CompoundCommand> for parser::CompoundCommand<'a> {
+	fn from(c: &'a CompoundCommand) -> parser::CompoundCommand<'a> {
+		match *c {
+			CompoundCommand::BraceGroup(ref list) => parser::CompoundCommand::BraceGroup(parser::List::from(list)),
+			CompoundCommand::If { ref conditions, ref otherwise } => parser::CompoundCommand::If {
+				conditions: conditions.iter().map(|&(ref c, ref b)| (parser::List::from(c), parser::List::from(b))).collect(),
+				otherwise: otherwise.as_ref().map(parser::List::from),
+			},
+			CompoundCommand::While { is_until, ref condition, ref body } => parser::CompoundCommand::While {
+				is_until: is_until, condition: parser::List::from(condition), body: parser::List::from(body),
+			},
+			CompoundCommand::Arithmetic(ref expr) => parser::CompoundCommand::Arithmetic(&expr.0),
+			CompoundCommand::Conditional(ref expr) => parser::CompoundCommand::Conditional(parser::CondExpr::from(expr)),
+		}
+	}
+}
+
+impl<'a> From<&'a CondExpr> for parser::CondExpr<'a> {
+	fn from(e: &'a CondExpr) -> parser::CondExpr<'a> {
+		let boxed = |e: &'a CondExpr| Box::new(parser::CondExpr::from(e));
+		match *e {
+			CondExpr::Word(ref w) => parser::CondExpr::Word(&w.0),
+			CondExpr::Unary(ref op, ref w) => parser::CondExpr::Unary(&op.0, &w.0),
+			CondExpr::Binary(ref l, ref op, ref r) => parser::CondExpr::Binary(&l.0, &op.0, &r.0),
+			CondExpr::Not(ref e) => parser::CondExpr::Not(boxed(e)),
+			CondExpr::And(ref l, ref r) => parser::CondExpr::And(boxed(l), boxed(r)),
+			CondExpr::Or(ref l, ref r) => parser::CondExpr::Or(boxed(l), boxed(r)),
+		}
+	}
+}
+
+impl<'a> From<&'a Command> for parser::Command<'a> {
+	fn from(c: &'a Command) -> parser::Command<'a> {
+		match *c {
+			Command::Simple(ref c) => parser::Command::Simple(parser::SimpleCommand::from(c)),
+			Command::Compound(ref c, ref r) => parser::Command::Compound(parser::CompoundCommand::from(c), parser_redirects(r)),
+		}
+	}
+}
+
+impl<'a> From<&'a Pipeline> for parser::Pipeline<'a> {
+	fn from(p: &'a Pipeline) -> parser::Pipeline<'a> {
+		parser::Pipeline {
+			commands: p.commands.iter().map(parser::Command::from).collect(),
+			is_negated: p.is_negated,
+			text: &p.text.0,
+			command_texts: word_slices(&p.command_texts),
+		}
+	}
+}
+
+impl<'a> From<&'a AndOr> for parser::AndOr<'a> {
+	fn from(a: &'a AndOr) -> parser::AndOr<'a> {
+		parser::AndOr {
+			first: parser::Pipeline::from(&a.first),
+			rest: a.rest.iter().map(|&(c, ref p)| (c, parser::Pipeline::from(p))).collect(),
+			is_background: a.is_background,
+			text: &a.text.0,
+		}
+	}
+}
+
+impl<'a> From<&'a List> for parser::List<'a> {
+	fn from(l: &'a List) -> parser::List<'a> {
+		parser::List { items: l.items.iter().map(parser::AndOr::from).collect() }
+	}
+}
@@ -1,42 +1,1045 @@
 use global;
+use options;
+use trap;
+use eval;
+use cond;
+use expand;
+use printf;
+use var;
+use dir;
+use error;
+use job;
+use search;
+use job::WaitStatusExt;
+use Shell;
 
-use std::{env,process,str};
+use std::{ffi,fmt,fs,io,str};
+use std::io::{Read,Write};
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd,RawFd};
+use std::rc::Rc;
+use std::time::{Duration,Instant};
+use nix;
 use nix::unistd;
+use nix::poll;
+use libc;
+use nix::sys::signal::Signal;
 
-const HOME_KEY: &'static str = "HOME";
+const HOME_KEY: &'static [u8] = b"HOME";
+const OLDPWD_KEY: &'static [u8] = b"OLDPWD";
+const CDPATH_KEY: &'static [u8] = b"CDPATH";
 
-pub fn builtin_cd(_: &mut global::State, args: &Vec<&[u8]>) -> u8 {
-	if args.len() > 2 { return 1; }
-	let r = match args.get(0) {
-		Some(&dir) => unistd::chdir(dir),
-		None => match env::var_os(HOME_KEY) {
-			Some(ref dir) => unistd::chdir(dir.as_os_str()),
-			None => { return 2; },
+fn scalar_var(state: &global::State, name: &[u8]) -> Option<Vec<u8>> {
+	match state.vars.get(name) {
+		Some(&var::Value::Scalar(ref s)) => Some(s.clone()),
+		Some(&var::Value::Array(ref a)) => a.get(0).cloned(),
+		None => None,
+	}
+}
+
+fn is_dir(path: &[u8]) -> bool {
+	use std::os::unix::ffi::OsStrExt;
+	fs::metadata(ffi::OsStr::from_bytes(path)).map(|m| m.is_dir()).unwrap_or(false)
+}
+
+/// Looks up a relative directory in `CDPATH`. Returns the path when it was found through a non-empty entry,
+/// in which case `cd` prints the new directory.
+fn search_cdpath(state: &global::State, dir: &[u8]) -> Option<Vec<u8>> {
+	if dir.is_empty() || dir.starts_with(b"/") || dir == b"." || dir == b".." || dir.starts_with(b"./") || dir.starts_with(b"../") {
+		return None;
+	}
+	let cdpath = scalar_var(state, CDPATH_KEY)?;
+	for entry in cdpath.split(|&c| c == b':') {
+		if entry.is_empty() {
+			if is_dir(dir) {
+				return None;
+			}
+			continue;
+		}
+		let mut path = entry.to_vec();
+		if !path.ends_with(b"/") {
+			path.push(b'/');
+		}
+		path.extend(dir);
+		if is_dir(&path) {
+			return Some(path);
 		}
+	}
+	None
+}
+
+fn builtin_cd(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let mut physical = false;
+	let mut args = &args[..];
+	while let Some((&arg, rest)) = args.split_first() {
+		if arg == b"--" {
+			args = rest;
+			break;
+		}
+		if arg.len() < 2 || arg[0] != b'-' {
+			break;
+		}
+		for &c in &arg[1 ..] {
+			match c {
+				b'L' => { physical = false; },
+				b'P' => { physical = true; },
+				_ => {
+					io.report("cd", format_args!("-{}: invalid option", c as char));
+					return 2;
+				},
+			}
+		}
+		args = rest;
+	}
+	if args.len() > 1 {
+		io.report("cd", "too many arguments");
+		return 1;
+	}
+
+	let mut print = false;
+	let dir = match args.get(0) {
+		None => match scalar_var(state, HOME_KEY) {
+			Some(home) => home,
+			None => {
+				io.report("cd", "HOME not set");
+				return 1;
+			},
+		},
+		Some(&b"-") => match scalar_var(state, OLDPWD_KEY) {
+			Some(oldpwd) => {
+				print = true;
+				oldpwd
+			},
+			None => {
+				io.report("cd", "OLDPWD not set");
+				return 1;
+			},
+		},
+		Some(&dir) => dir.to_vec(),
 	};
-	if let Err(e) = r {
-		use std::error::Error;
-		println!("{}", e.description());
-		return 3;
+	let target = match search_cdpath(state, &dir) {
+		Some(path) => {
+			print = true;
+			path
+		},
+		None => dir.clone(),
+	};
+	if let Err(e) = dir::change_dir(state, &target, physical) {
+		io.report("cd", format_args!("{}: {}", String::from_utf8_lossy(&dir), error::describe_nix(&e)));
+		return 1;
+	}
+	if print {
+		let mut line = state.dirs.pwd.clone();
+		line.push(b'\n');
+		return write_stdout(io, "cd", &line);
 	}
 	0
 }
 
-pub fn builtin_exit(_: &mut global::State, args: &Vec<&[u8]>) -> u8 {
-	let s = args.get(0).and_then(|&a| str::from_utf8(a).ok()).and_then(|s| s.parse().ok()).unwrap_or(0);
-	process::exit(s);
+fn builtin_pwd(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	use std::os::unix::ffi::OsStringExt;
+	let mut line = match args.last() {
+		Some(&b"-P") => match unistd::getcwd() {
+			Ok(path) => path.into_os_string().into_vec(),
+			Err(e) => {
+				io.report("pwd", error::describe_nix(&e));
+				return 1;
+			},
+		},
+		None | Some(&b"-L") => state.dirs.pwd.clone(),
+		Some(arg) => {
+			io.report("pwd", format_args!("{}: invalid option", String::from_utf8_lossy(arg)));
+			return 2;
+		},
+	};
+	line.push(b'\n');
+	write_stdout(io, "pwd", &line)
+}
+
+/// Returns the entries of the directory stack, the current directory first.
+fn dir_stack(state: &global::State) -> Vec<Vec<u8>> {
+	let mut stack = vec![state.dirs.pwd.clone()];
+	stack.extend(state.dirs.stack.iter().cloned());
+	stack
 }
 
-pub fn builtin_rehash(state: &mut global::State, _: &Vec<&[u8]>) -> u8 {
-	state.search_cache.rehash();
+/// Parses a `+N` or `-N` argument of `pushd`, `popd` and `dirs` into an index of `dir_stack`.
+/// Returns `None` if the argument has another form.
+fn stack_index(state: &global::State, io: &mut Io, name: &str, arg: &[u8]) -> Option<Result<usize, ()>> {
+	let from_left = match arg.first() {
+		Some(&b'+') => true,
+		Some(&b'-') => false,
+		_ => { return None; },
+	};
+	let n: usize = str::from_utf8(&arg[1 ..]).ok()?.parse().ok()?;
+	let len = state.dirs.stack.len() + 1;
+	if n >= len {
+		io.report(name, format_args!("{}: directory stack index out of range", String::from_utf8_lossy(arg)));
+		return Some(Err(()));
+	}
+	Some(Ok(if from_left { n } else { len - 1 - n }))
+}
+
+fn print_dir_stack(state: &global::State, io: &mut Io, name: &str, long: bool, per_line: bool, numbered: bool) -> u8 {
+	let mut out = vec![];
+	for (i, dir) in dir_stack(state).iter().enumerate() {
+		let dir = if long { dir.clone() } else { dir::abbreviate_home(state, dir) };
+		if numbered {
+			out.extend(format!("{:2}  ", i).into_bytes());
+		} else if i > 0 {
+			out.push(if per_line { b'\n' } else { b' ' });
+		}
+		out.extend(dir);
+		if numbered {
+			out.push(b'\n');
+		}
+	}
+	if !numbered {
+		out.push(b'\n');
+	}
+	write_stdout(io, name, &out)
+}
+
+/// Makes `stack` the directory stack after changing to its first entry.
+fn set_dir_stack(state: &mut global::State, io: &mut Io, name: &str, mut stack: Vec<Vec<u8>>) -> u8 {
+	if let Err(e) = dir::change_dir(state, &stack[0], false) {
+		io.report(name, format_args!("{}: {}", String::from_utf8_lossy(&stack[0]), error::describe_nix(&e)));
+		return 1;
+	}
+	stack.remove(0);
+	state.dirs.stack = stack;
+	print_dir_stack(state, io, name, false, false, false)
+}
+
+fn builtin_pushd(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	if args.len() > 1 {
+		io.report("pushd", "too many arguments");
+		return 1;
+	}
+	let mut stack = dir_stack(state);
+	match args.get(0) {
+		None => {
+			if stack.len() < 2 {
+				io.report("pushd", "no other directory");
+				return 1;
+			}
+			stack.swap(0, 1);
+			set_dir_stack(state, io, "pushd", stack)
+		},
+		Some(&arg) => match stack_index(state, io, "pushd", arg) {
+			Some(Ok(n)) => {
+				stack.rotate_left(n);
+				set_dir_stack(state, io, "pushd", stack)
+			},
+			Some(Err(())) => 1,
+			None => {
+				let oldpwd = state.dirs.pwd.clone();
+				if let Err(e) = dir::change_dir(state, arg, false) {
+					io.report("pushd", format_args!("{}: {}", String::from_utf8_lossy(arg), error::describe_nix(&e)));
+					return 1;
+				}
+				state.dirs.stack.insert(0, oldpwd);
+				print_dir_stack(state, io, "pushd", false, false, false)
+			},
+		},
+	}
+}
+
+fn builtin_popd(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	if args.len() > 1 {
+		io.report("popd", "too many arguments");
+		return 1;
+	}
+	if state.dirs.stack.is_empty() {
+		io.report("popd", "directory stack empty");
+		return 1;
+	}
+	let n = match args.get(0).map(|&arg| (arg, stack_index(state, io, "popd", arg))) {
+		None => 0,
+		Some((_, Some(Ok(n)))) => n,
+		Some((_, Some(Err(())))) => { return 1; },
+		Some((arg, None)) => {
+			io.report("popd", format_args!("{}: invalid argument", String::from_utf8_lossy(arg)));
+			return 2;
+		},
+	};
+	let mut stack = dir_stack(state);
+	stack.remove(n);
+	if n == 0 {
+		set_dir_stack(state, io, "popd", stack)
+	} else {
+		stack.remove(0);
+		state.dirs.stack = stack;
+		print_dir_stack(state, io, "popd", false, false, false)
+	}
+}
+
+fn builtin_dirs(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let (mut long, mut per_line, mut numbered) = (false, false, false);
+	for &arg in args {
+		if arg.len() < 2 || arg[0] != b'-' {
+			io.report("dirs", format_args!("{}: invalid argument", String::from_utf8_lossy(arg)));
+			return 2;
+		}
+		for &c in &arg[1 ..] {
+			match c {
+				b'c' => { state.dirs.stack.clear(); },
+				b'l' => { long = true; },
+				b'p' => { per_line = true; },
+				b'v' => { numbered = true; },
+				_ => {
+					io.report("dirs", format_args!("-{}: invalid option", c as char));
+					return 2;
+				},
+			}
+		}
+	}
+	if args.iter().any(|arg| arg.contains(&b'c')) {
+		return 0;
+	}
+	print_dir_stack(state, io, "dirs", long, per_line, numbered)
+}
+
+fn builtin_exit(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let s = match args.get(0) {
+		None => state.last_status,
+		Some(&arg) => match str::from_utf8(arg).ok().and_then(|s| s.parse::<i32>().ok()) {
+			Some(s) => s as u8,
+			None => {
+				io.report("exit", format_args!("{}: numeric argument required", String::from_utf8_lossy(arg)));
+				2
+			},
+		},
+	};
+	eval::request_exit(state, s);
+	s
+}
+
+/// Replaces the shell with a command. Redirections without a command are handled by `eval`.
+fn builtin_exec(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let args = match args.split_first() {
+		Some((&b"--", rest)) => rest,
+		_ => &args[..],
+	};
+	if args.is_empty() {
+		return 0;
+	}
+	trap::reset_dispositions();
+	let _ = io::stdout().flush();
+	let s = match eval::exec_external(state, args.iter().map(|a| a.to_vec()).collect()) {
+		Ok(s) => s,
+		Err(e) => {
+			io.report("exec", &e);
+			e.status()
+		},
+	};
+	if !state.options.interactive {
+		eval::request_exit(state, s);
+	}
+	state.traps.reinstall();
+	s
+}
+
+fn builtin_rehash(state: &mut global::State, _: &mut Io, _: &[&[u8]]) -> u8 {
+	state.search_cache.rehash(state.vars.get_str(search::PATH_KEY));
+	0
+}
+
+fn print_options(state: &global::State, io: &mut Io, as_commands: bool) {
+	for &(name, _) in options::OPTION_NAMES {
+		let enabled = state.options.get(name);
+		let _ = if as_commands {
+			writeln!(io.stdout, "set {}o {}", if enabled { '-' } else { '+' }, name)
+		} else {
+			writeln!(io.stdout, "{:<15}\t{}", name, if enabled { "on" } else { "off" })
+		};
+	}
+}
+
+/// Sets options, and the positional parameters from the first argument that is not an option or from `--`.
+fn builtin_set(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let mut args = args.iter();
+	while let Some(&arg) = args.clone().next() {
+		let enable = match arg.first() {
+			Some(&b'-') if arg != b"-" => true,
+			Some(&b'+') if arg != b"+" => false,
+			_ => {
+				state.args.truncate(1);
+				state.args.extend(args.map(|a| a.to_vec()));
+				return 0;
+			},
+		};
+		args.next();
+		if arg == b"--" {
+			state.args.truncate(1);
+			state.args.extend(args.map(|a| a.to_vec()));
+			return 0;
+		}
+		if &arg[1 ..] == b"o" {
+			match args.next() {
+				Some(&name) => if !state.options.set(name, enable) {
+					io.report("set", format_args!("invalid option name: {}", String::from_utf8_lossy(name)));
+					return 2;
+				},
+				None => print_options(state, io, !enable),
+			}
+			continue;
+		}
+		for &c in &arg[1 ..] {
+			if !state.options.set_letter(c, enable) {
+				io.report("set", format_args!("invalid option: {}{}", arg[0] as char, c as char));
+				return 2;
+			}
+		}
+	}
+	0
+}
+
+fn quote(s: &[u8]) -> Vec<u8> {
+	let mut quoted = vec![b'\''];
+	for &c in s {
+		if c == b'\'' {
+			quoted.extend(b"'\\''");
+		} else {
+			quoted.push(c);
+		}
+	}
+	quoted.push(b'\'');
+	quoted
+}
+
+fn builtin_shift(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let n = match args.get(0) {
+		None => 1,
+		Some(&arg) => match str::from_utf8(arg).ok().and_then(|s| s.parse::<usize>().ok()) {
+			Some(n) => n,
+			None => {
+				io.report("shift", format_args!("{}: numeric argument required", String::from_utf8_lossy(arg)));
+				return 2;
+			},
+		},
+	};
+	if n >= state.args.len() {
+		return 1;
+	}
+	state.args.drain(1 .. n + 1);
+	0
+}
+
+fn builtin_unset(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let args = match args.split_first() {
+		Some((&b"-v", rest)) | Some((&b"--", rest)) => rest,
+		_ => &args[..],
+	};
+	let mut s = 0;
+	for &name in args {
+		if expand::is_name(name) {
+			state.vars.unset(name);
+		} else {
+			io.report("unset", format_args!("`{}': not a valid identifier", String::from_utf8_lossy(name)));
+			s = 1;
+		}
+	}
+	s
+}
+
+fn builtin_trap(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let mut args = &args[..];
+	match args.first() {
+		None | Some(&b"-p") => {
+			for (cond, action) in state.traps.iter() {
+				let _ = io.stdout.write_all(b"trap -- ");
+				let _ = io.stdout.write_all(&quote(action));
+				let _ = writeln!(io.stdout, " {}", cond.name());
+			}
+			return 0;
+		},
+		Some(&b"-l") => {
+			for sig in Signal::iterator() {
+				let _ = writeln!(io.stdout, "{:2}) SIG{}", sig as i32, trap::signal_name(sig));
+			}
+			return 0;
+		},
+		Some(&b"--") => { args = &args[1 ..]; },
+		_ => {},
+	}
+	// A lone condition, or `-` as the action, restores the default.
+	let (action, conds) = match args.split_first() {
+		Some((&b"-", conds)) => (None, conds),
+		Some((&action, conds)) if !conds.is_empty() => (Some(action.to_vec()), conds),
+		_ => (None, args),
+	};
+	let mut s = 0;
+	for &cond in conds {
+		let r = match trap::Condition::parse(cond) {
+			Some(c) => state.traps.set(c, action.clone()).is_ok(),
+			None => false,
+		};
+		if !r {
+			io.report("trap", format_args!("{}: invalid signal specification", String::from_utf8_lossy(cond)));
+			s = 1;
+		}
+	}
+	s
+}
+
+fn builtin_let(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	if args.is_empty() {
+		io.report("let", "expression expected");
+		return 1;
+	}
+	let mut last = 0;
+	for &arg in args {
+		match expand::eval_arithmetic(state, arg) {
+			Ok(n) => { last = n; },
+			Err(e) => {
+				io.report("let", e);
+				return 1;
+			},
+		}
+	}
+	if last != 0 { 0 } else { 1 }
+}
+
+fn builtin_test(_: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	match cond::test(args) {
+		Ok(true) => 0,
+		Ok(false) => 1,
+		Err(e) => {
+			io.report("test", e);
+			2
+		},
+	}
+}
+
+fn builtin_bracket(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	match args.split_last() {
+		Some((&b"]", args)) => builtin_test(state, io, args),
+		_ => {
+			io.report("[", "missing ']'");
+			2
+		},
+	}
+}
+
+/// Writes the output of a builtin, and reports a write error.
+fn write_stdout(io: &mut Io, name: &str, s: &[u8]) -> u8 {
+	match io.stdout.write_all(s) {
+		Ok(_) => 0,
+		Err(e) => {
+			io.report(name, format_args!("write error: {}", error::describe_io(&e)));
+			1
+		},
+	}
+}
+
+fn builtin_true(_: &mut global::State, _: &mut Io, _: &[&[u8]]) -> u8 {
 	0
 }
 
-pub fn match_builtin(name: &[u8]) -> Option<fn(&mut global::State, &Vec<&[u8]>) -> u8> {
-	match name {
-		b"cd" => Some(builtin_cd),
-		b"exit" => Some(builtin_exit),
-		b"rehash" => Some(builtin_rehash),
-		_ => None,
+fn builtin_false(_: &mut global::State, _: &mut Io, _: &[&[u8]]) -> u8 {
+	1
+}
+
+fn builtin_echo(_: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let mut newline = true;
+	let mut escapes = false;
+	let mut args = &args[..];
+	while let Some((&arg, rest)) = args.split_first() {
+		if arg.len() < 2 || arg[0] != b'-' || !arg[1 ..].iter().all(|c| b"neE".contains(c)) {
+			break;
+		}
+		for &c in &arg[1 ..] {
+			match c {
+				b'n' => { newline = false; },
+				b'e' => { escapes = true; },
+				_ => { escapes = false; },
+			}
+		}
+		args = rest;
+	}
+	let mut out = vec![];
+	for (i, &arg) in args.iter().enumerate() {
+		if i > 0 {
+			out.push(b' ');
+		}
+		if !escapes {
+			out.extend(arg);
+		} else if printf::decode_argument(arg, &mut out) {
+			return write_stdout(io, "echo", &out);
+		}
+	}
+	if newline {
+		out.push(b'\n');
+	}
+	write_stdout(io, "echo", &out)
+}
+
+fn builtin_printf(_: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let args = match args.split_first() {
+		Some((&b"--", rest)) => rest,
+		_ => &args[..],
+	};
+	let (format, args) = match args.split_first() {
+		Some((&format, args)) => (format, args),
+		None => {
+			io.report("printf", "usage: printf format [arguments]");
+			return 2;
+		},
+	};
+	let (out, errors) = printf::format(format, args);
+	let s = write_stdout(io, "printf", &out);
+	for e in &errors {
+		io.report("printf", e);
+	}
+	if errors.is_empty() { s } else { 1 }
+}
+
+const REPLY_KEY: &'static [u8] = b"REPLY";
+
+/// Waits until `fd` is readable. Returns `false` when `deadline` passes first.
+fn wait_readable(fd: RawFd, deadline: Instant) -> nix::Result<bool> {
+	let now = Instant::now();
+	let remaining = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+	let ms = remaining.as_secs() as i64 * 1000 + (remaining.subsec_nanos() / 1_000_000) as i64;
+	let mut fds = [poll::PollFd::new(fd, poll::POLLIN, poll::EventFlags::empty())];
+	loop {
+		match poll::poll(&mut fds, ::std::cmp::min(ms, libc::c_int::max_value() as i64) as libc::c_int) {
+			Err(nix::Error::Sys(nix::Errno::EINTR)) => {},
+			r => { return r.map(|n| n > 0); },
+		}
+	}
+}
+
+fn builtin_read(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let mut raw = false;
+	let mut prompt: &[u8] = b"";
+	let mut timeout = None;
+	let mut delimiter = b'\n';
+	let mut i = 0;
+	while i < args.len() && args[i].len() > 1 && args[i][0] == b'-' {
+		let arg = args[i];
+		i += 1;
+		if arg == b"--" {
+			break;
+		}
+		for (n, &c) in arg.iter().enumerate().skip(1) {
+			match c {
+				b'r' => { raw = true; continue; },
+				b'p' | b't' | b'd' => {},
+				_ => {
+					io.report("read", format_args!("-{}: invalid option", c as char));
+					return 2;
+				},
+			}
+			let value = if n + 1 < arg.len() {
+				&arg[n + 1 ..]
+			} else if i < args.len() {
+				i += 1;
+				args[i - 1]
+			} else {
+				io.report("read", format_args!("-{}: option requires an argument", c as char));
+				return 2;
+			};
+			match c {
+				b'p' => { prompt = value; },
+				b'd' => { delimiter = value.get(0).cloned().unwrap_or(b'\0'); },
+				_ => match String::from_utf8_lossy(value).parse::<f64>() {
+					Ok(t) if t >= 0.0 => { timeout = Some(t); },
+					_ => {
+						io.report("read", format_args!("{}: invalid timeout specification", String::from_utf8_lossy(value)));
+						return 2;
+					},
+				},
+			}
+			break;
+		}
+	}
+	let names = &args[i ..];
+	if let Some(name) = names.iter().find(|name| !expand::is_name(name)) {
+		io.report("read", format_args!("`{}': not a valid identifier", String::from_utf8_lossy(name)));
+		return 1;
+	}
+
+	if !prompt.is_empty() && unistd::isatty(io.stdin.as_raw_fd()).unwrap_or(false) {
+		let _ = io.stderr.write_all(prompt);
+	}
+	// A timeout too long for an `Instant`, such as `inf`, is no deadline at all.
+	let deadline = timeout.and_then(|t| Instant::now().checked_add(Duration::new(t as u64, ((t - t.trunc()) * 1e9) as u32)));
+	if timeout == Some(0.0) {
+		return match wait_readable(io.stdin.as_raw_fd(), deadline.unwrap()) {
+			Ok(true) => 0,
+			_ => 1,
+		};
+	}
+
+	// Input is read a byte at a time so that what follows the line is left for the next command.
+	let mut line = vec![];
+	let mut is_literal = vec![];
+	let mut escaped = false;
+	let mut s = 0;
+	loop {
+		if let Some(deadline) = deadline {
+			match wait_readable(io.stdin.as_raw_fd(), deadline) {
+				Ok(true) => {},
+				Ok(false) => {
+					s = 128 + libc::SIGALRM as u8;
+					break;
+				},
+				Err(e) => {
+					io.report("read", error::describe_nix(&e));
+					return 1;
+				},
+			}
+		}
+		let mut buf = [0u8];
+		match io.stdin.read(&mut buf) {
+			Ok(0) => {
+				s = 1;
+				break;
+			},
+			Ok(_) => {},
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => { continue; },
+			Err(e) => {
+				io.report("read", error::describe_io(&e));
+				return 1;
+			},
+		}
+		let c = buf[0];
+		if escaped {
+			escaped = false;
+			if c != b'\n' {
+				line.push(c);
+				is_literal.push(true);
+			}
+		} else if c == b'\\' && !raw {
+			escaped = true;
+		} else if c == delimiter {
+			break;
+		} else {
+			line.push(c);
+			is_literal.push(false);
+		}
+	}
+
+	if names.is_empty() {
+		state.vars.set_scalar(REPLY_KEY, &line);
+		return s;
+	}
+	let ifs = expand::ifs(state);
+	let mut fields = expand::split_fields(&line, &is_literal, &ifs, Some(names.len())).into_iter();
+	for name in names {
+		state.vars.set_scalar(name, &fields.next().unwrap_or(vec![]));
 	}
+	s
+}
+
+fn builtin_jobs(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	let (mut long, mut pids) = (false, false);
+	for &arg in args {
+		if arg.len() < 2 || arg[0] != b'-' {
+			io.report("jobs", format_args!("{}: invalid argument", String::from_utf8_lossy(arg)));
+			return 2;
+		}
+		for &c in &arg[1 ..] {
+			match c {
+				b'l' => { long = true; },
+				b'p' => { pids = true; },
+				_ => {
+					io.report("jobs", format_args!("-{}: invalid option", c as char));
+					return 2;
+				},
+			}
+		}
+	}
+	state.job_set.reap();
+	let mut out = String::new();
+	// Jobs that are done are listed once, and then forgotten.
+	for event in state.job_set.take_events() {
+		if !pids && event.status.state() == job::State::Terminated {
+			out.push_str(&event.describe());
+			out.push('\n');
+		}
+	}
+	for (id, mark, job) in state.job_set.jobs() {
+		if pids {
+			out.push_str(&format!("{}\n", job.gid));
+		} else if !long {
+			out.push_str(&job::describe_job(id, mark, job.status(), &job.text));
+			out.push('\n');
+		} else {
+			// Each process on its own line with its pid and status, like `ps`.
+			let header = format!("[{}]{} ", id, mark);
+			for (i, pr) in job.proccesses.iter().enumerate() {
+				let status = job::describe_status(pr.status);
+				let text = String::from_utf8_lossy(&pr.text);
+				if i == 0 {
+					out.push_str(&format!("{}{:>5} {:<24}{}\n", header, pr.pid, status, text));
+				} else {
+					out.push_str(&format!("{:w$}{:>5} {:<24}| {}\n", "", pr.pid, status, text, w = header.len()));
+				}
+			}
+		}
+	}
+	write_stdout(io, "jobs", out.as_bytes())
+}
+
+fn builtin_fg(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	if args.len() > 1 {
+		io.report("fg", "too many arguments");
+		return 2;
+	}
+	if state.job_set.terminal().is_none() {
+		io.report("fg", "no job control");
+		return 1;
+	}
+	let modes = state.terminal_modes;
+	let mut job_desc = match state.job_set.find(args.get(0).cloned()) {
+		Some(job_desc) => job_desc,
+		None => {
+			io.report("fg", format_args!("{}: no such job", args.get(0).map_or("current".into(), |s| String::from_utf8_lossy(s))));
+			return 1;
+		},
+	};
+	let _ = writeln!(io.stdout, "{}", String::from_utf8_lossy(&job_desc.job().text));
+	if let Err(e) = job_desc.resume(true) {
+		io.report("fg", error::describe_nix(&e));
+		return 1;
+	}
+	*eval::wait_foreground(&mut job_desc, modes).last().unwrap()
+}
+
+fn builtin_bg(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	if state.job_set.terminal().is_none() {
+		io.report("bg", "no job control");
+		return 1;
+	}
+	let specs: Vec<Option<&[u8]>> = if args.is_empty() { vec![None] } else { args.iter().map(|&a| Some(a)).collect() };
+	let mut s = 0;
+	for spec in specs {
+		let mut job_desc = match state.job_set.find(spec) {
+			Some(job_desc) => job_desc,
+			None => {
+				io.report("bg", format_args!("{}: no such job", spec.map_or("current".into(), String::from_utf8_lossy)));
+				s = 1;
+				continue;
+			},
+		};
+		if let Err(e) = job_desc.resume(false) {
+			io.report("bg", error::describe_nix(&e));
+			s = 1;
+			continue;
+		}
+		let _ = writeln!(io.stdout, "[{}] {} &", job_desc.id(), String::from_utf8_lossy(&job_desc.job().text));
+	}
+	s
+}
+
+fn builtin_help(state: &mut global::State, io: &mut Io, args: &[&[u8]]) -> u8 {
+	if args.is_empty() {
+		let mut out = vec![];
+		for name in state.builtins.names() {
+			let builtin = state.builtins.get(name).unwrap();
+			match builtin.help().lines().next() {
+				Some(usage) => out.extend(usage.as_bytes()),
+				None => out.extend(name),
+			}
+			out.push(b'\n');
+		}
+		return write_stdout(io, "help", &out);
+	}
+	let mut s = 0;
+	for &name in args {
+		match state.builtins.get(name) {
+			Some(ref builtin) if !builtin.help().is_empty() => {
+				let mut help = builtin.help().to_string();
+				if !help.ends_with('\n') {
+					help.push('\n');
+				}
+				s = write_stdout(io, "help", help.as_bytes()).max(s);
+			},
+			_ => {
+				io.report("help", format_args!("{}: no help topics match", String::from_utf8_lossy(name)));
+				s = 1;
+			},
+		}
+	}
+	s
+}
+
+/// A standard stream of a builtin. It is one of the shell's fds 0, 1 and 2, so it follows the redirections of the builtin.
+/// Writes are not buffered, so that they are not reordered with the output of children.
+pub struct Stream {
+	fd: RawFd,
+}
+
+impl Read for Stream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = unsafe{ libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+		if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+	}
+}
+
+impl Write for Stream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let n = unsafe{ libc::write(self.fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+		if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl AsRawFd for Stream {
+	fn as_raw_fd(&self) -> RawFd {
+		self.fd
+	}
+}
+
+/// The standard input, output and error of a builtin.
+pub struct Io {
+	pub stdin: Stream,
+	pub stdout: Stream,
+	pub stderr: Stream,
+}
+
+impl Io {
+	pub fn new() -> Io {
+		Io { stdin: Stream { fd: libc::STDIN_FILENO }, stdout: Stream { fd: libc::STDOUT_FILENO }, stderr: Stream { fd: libc::STDERR_FILENO } }
+	}
+
+	/// Writes `ish: <context>: <message>` to the builtin's stderr.
+	pub fn report<C: fmt::Display, M: fmt::Display>(&mut self, context: C, message: M) {
+		let _ = writeln!(self.stderr, "ish: {}: {}", context, message);
+	}
+}
+
+/// What the arguments of a builtin are, for completing them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompletionHint {
+	/// The arguments are not worth completing.
+	Nothing,
+	Files,
+	Directories,
+	/// Names of shell variables.
+	Variables,
+	/// Signal names and the other conditions of `trap`.
+	Signals,
+	/// Names of builtins.
+	Builtins,
+	/// One of a fixed set of words.
+	Words(&'static [&'static str]),
+}
+
+/// A command that runs in the shell process.
+pub trait Builtin {
+	/// Runs the command with its arguments, without its name, and returns its exit status.
+	fn run(&self, shell: &mut Shell, io: &mut Io, args: &[&[u8]]) -> u8;
+
+	/// A usage line, optionally followed by a description. It is shown by `help`.
+	fn help(&self) -> &str {
+		""
+	}
+
+	fn completion(&self) -> CompletionHint {
+		CompletionHint::Files
+	}
+}
+
+impl<F> Builtin for F where F: Fn(&mut Shell, &mut Io, &[&[u8]]) -> u8 {
+	fn run(&self, shell: &mut Shell, io: &mut Io, args: &[&[u8]]) -> u8 {
+		self(shell, io, args)
+	}
+}
+
+/// One of the shell's own builtins.
+struct Internal {
+	run: fn(&mut global::State, &mut Io, &[&[u8]]) -> u8,
+	help: &'static str,
+	completion: CompletionHint,
+}
+
+impl Builtin for Internal {
+	fn run(&self, shell: &mut Shell, io: &mut Io, args: &[&[u8]]) -> u8 {
+		(self.run)(&mut shell.state, io, args)
+	}
+
+	fn help(&self) -> &str {
+		self.help
+	}
+
+	fn completion(&self) -> CompletionHint {
+		self.completion
+	}
+}
+
+const SET_WORDS: &'static [&'static str] = &["-o", "+o", "--"];
+
+const INTERNAL_BUILTINS: &'static [(&'static [u8], Internal)] = &[
+	(b":", Internal { run: builtin_true, help: ": [arguments]\nDoes nothing and succeeds.", completion: CompletionHint::Files }),
+	(b"[", Internal { run: builtin_bracket, help: "[ expression ]\nEvaluates a conditional expression like `test`.", completion: CompletionHint::Files }),
+	(b"bg", Internal { run: builtin_bg, help: "bg [job ...]\nContinues stopped jobs in the background.", completion: CompletionHint::Nothing }),
+	(b"cd", Internal { run: builtin_cd, help: "cd [-L|-P] [directory | -]\nChanges the current directory, searching CDPATH for relative names.", completion: CompletionHint::Directories }),
+	(b"dirs", Internal { run: builtin_dirs, help: "dirs [-clpv]\nPrints or clears the directory stack.", completion: CompletionHint::Words(&["-c", "-l", "-p", "-v"]) }),
+	(b"echo", Internal { run: builtin_echo, help: "echo [-neE] [arguments]\nWrites the arguments separated by spaces.", completion: CompletionHint::Files }),
+	(b"exec", Internal { run: builtin_exec, help: "exec [command [arguments]]\nReplaces the shell with a command, or makes redirections permanent.", completion: CompletionHint::Files }),
+	(b"exit", Internal { run: builtin_exit, help: "exit [status]\nExits the shell.", completion: CompletionHint::Nothing }),
+	(b"false", Internal { run: builtin_false, help: "false\nFails.", completion: CompletionHint::Nothing }),
+	(b"fg", Internal { run: builtin_fg, help: "fg [job]\nContinues a job in the foreground, with the terminal modes it had when it stopped.", completion: CompletionHint::Nothing }),
+	(b"help", Internal { run: builtin_help, help: "help [builtin ...]\nDescribes builtins, or lists them without arguments.", completion: CompletionHint::Builtins }),
+	(b"jobs", Internal { run: builtin_jobs, help: "jobs [-lp]\nLists the jobs with their commands, with -l their processes, or with -p their process groups.", completion: CompletionHint::Words(&["-l", "-p"]) }),
+	(b"let", Internal { run: builtin_let, help: "let expression ...\nEvaluates arithmetic expressions, and succeeds if the last one is not 0.", completion: CompletionHint::Variables }),
+	(b"popd", Internal { run: builtin_popd, help: "popd [+N | -N]\nRemoves a directory from the directory stack.", completion: CompletionHint::Nothing }),
+	(b"printf", Internal { run: builtin_printf, help: "printf format [arguments]\nWrites the arguments according to a format.", completion: CompletionHint::Nothing }),
+	(b"pushd", Internal { run: builtin_pushd, help: "pushd [directory | +N | -N]\nAdds a directory to the directory stack, or rotates the stack.", completion: CompletionHint::Directories }),
+	(b"pwd", Internal { run: builtin_pwd, help: "pwd [-L|-P]\nPrints the current directory.", completion: CompletionHint::Words(&["-L", "-P"]) }),
+	(b"read", Internal { run: builtin_read, help: "read [-r] [-p prompt] [-t timeout] [-d delimiter] [name ...]\nReads a line and splits it into variables.", completion: CompletionHint::Variables }),
+	(b"rehash", Internal { run: builtin_rehash, help: "rehash\nForgets the locations of commands found in PATH.", completion: CompletionHint::Nothing }),
+	(b"set", Internal { run: builtin_set, help: "set [-+o option] [-+flags] [--] [arguments]\nSets options and the positional parameters.", completion: CompletionHint::Words(SET_WORDS) }),
+	(b"shift", Internal { run: builtin_shift, help: "shift [n]\nRemoves the first positional parameters.", completion: CompletionHint::Nothing }),
+	(b"test", Internal { run: builtin_test, help: "test expression\nEvaluates a conditional expression.", completion: CompletionHint::Files }),
+	(b"trap", Internal { run: builtin_trap, help: "trap [-lp] [[action] condition ...]\nRuns an action when the shell receives a signal or exits.", completion: CompletionHint::Signals }),
+	(b"true", Internal { run: builtin_true, help: "true\nSucceeds.", completion: CompletionHint::Nothing }),
+	(b"unset", Internal { run: builtin_unset, help: "unset name ...\nRemoves variables.", completion: CompletionHint::Variables }),
+];
+
+/// The builtins by name: the shell's own, and those registered by an embedder, which replace them.
+pub struct Registry {
+	builtins: HashMap<Vec<u8>, Rc<Builtin>>,
+}
+
+impl Registry {
+	pub fn new() -> Registry {
+		let mut registry = Registry { builtins: HashMap::new() };
+		for &(name, ref internal) in INTERNAL_BUILTINS {
+			registry.insert(name, Rc::new(Internal { run: internal.run, help: internal.help, completion: internal.completion }));
+		}
+		registry
+	}
+
+	/// Adds a builtin, and returns the one it replaces.
+	pub fn insert(&mut self, name: &[u8], builtin: Rc<Builtin>) -> Option<Rc<Builtin>> {
+		self.builtins.insert(name.to_vec(), builtin)
+	}
+
+	pub fn remove(&mut self, name: &[u8]) -> Option<Rc<Builtin>> {
+		self.builtins.remove(name)
+	}
+
+	pub fn get(&self, name: &[u8]) -> Option<Rc<Builtin>> {
+		self.builtins.get(name).cloned()
+	}
+
+	/// The names of the builtins in byte order.
+	pub fn names(&self) -> Vec<&[u8]> {
+		let mut names: Vec<&[u8]> = self.builtins.keys().map(|k| &k[..]).collect();
+		names.sort();
+		names
+	}
+}
+
+/// Runs a builtin with the shell's current standard streams.
+pub fn run(state: &mut global::State, builtin: &Builtin, args: &[&[u8]]) -> u8 {
+	builtin.run(Shell::from_state(state), &mut Io::new(), args)
 }
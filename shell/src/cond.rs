@@ -0,0 +1,224 @@
+//! The primaries shared by the `test` and `[` builtins and the `[[ ]]` command.
+
+use std::{fs,mem};
+use std::ffi::CString;
+use std::os::unix::fs::{FileTypeExt,MetadataExt,PermissionsExt};
+use std::os::unix::ffi::OsStrExt;
+use std::ffi::OsStr;
+use libc;
+
+pub type CondResult<T> = Result<T, String>;
+
+const UNARY_OPERATORS: &'static [&'static [u8]] = &[
+	b"-b", b"-c", b"-d", b"-e", b"-f", b"-g", b"-h", b"-k", b"-L", b"-n", b"-p", b"-r", b"-s", b"-S", b"-t", b"-u", b"-w", b"-x", b"-z",
+];
+
+const BINARY_OPERATORS: &'static [&'static [u8]] = &[
+	b"=", b"==", b"!=", b"<", b">", b"-eq", b"-ne", b"-lt", b"-le", b"-gt", b"-ge", b"-nt", b"-ot", b"-ef",
+];
+
+pub fn is_unary_operator(op: &[u8]) -> bool {
+	UNARY_OPERATORS.contains(&op)
+}
+
+pub fn is_binary_operator(op: &[u8]) -> bool {
+	BINARY_OPERATORS.contains(&op)
+}
+
+fn to_cstring(s: &[u8]) -> CString {
+	CString::new(s.iter().cloned().take_while(|&c| c != 0).collect::<Vec<u8>>()).unwrap()
+}
+
+fn access(path: &[u8], mode: libc::c_int) -> bool {
+	unsafe{ libc::access(to_cstring(path).as_ptr(), mode) == 0 }
+}
+
+pub fn unary(op: &[u8], arg: &[u8]) -> bool {
+	let path = OsStr::from_bytes(arg);
+	match op {
+		b"-n" => !arg.is_empty(),
+		b"-z" => arg.is_empty(),
+		b"-h" | b"-L" => fs::symlink_metadata(path).map(|m| m.file_type().is_symlink()).unwrap_or(false),
+		b"-r" => access(arg, libc::R_OK),
+		b"-w" => access(arg, libc::W_OK),
+		b"-x" => access(arg, libc::X_OK),
+		b"-t" => match String::from_utf8_lossy(arg).trim().parse() {
+			Ok(fd) => unsafe{ libc::isatty(fd) == 1 },
+			Err(_) => false,
+		},
+		_ => match fs::metadata(path) {
+			Err(_) => false,
+			Ok(m) => match op {
+				b"-e" => true,
+				b"-f" => m.is_file(),
+				b"-d" => m.is_dir(),
+				b"-s" => m.len() > 0,
+				b"-b" => m.file_type().is_block_device(),
+				b"-c" => m.file_type().is_char_device(),
+				b"-p" => m.file_type().is_fifo(),
+				b"-S" => m.file_type().is_socket(),
+				b"-g" => m.permissions().mode() & 0o2000 != 0,
+				b"-u" => m.permissions().mode() & 0o4000 != 0,
+				b"-k" => m.permissions().mode() & 0o1000 != 0,
+				_ => false,
+			},
+		},
+	}
+}
+
+fn integer(s: &[u8]) -> CondResult<i64> {
+	String::from_utf8_lossy(s).trim().parse().map_err(|_| format!("{}: integer expression expected", String::from_utf8_lossy(s)))
+}
+
+pub fn binary(left: &[u8], op: &[u8], right: &[u8]) -> CondResult<bool> {
+	Ok(match op {
+		b"=" | b"==" => left == right,
+		b"!=" => left != right,
+		b"<" => left < right,
+		b">" => left > right,
+		b"-eq" => integer(left)? == integer(right)?,
+		b"-ne" => integer(left)? != integer(right)?,
+		b"-lt" => integer(left)? < integer(right)?,
+		b"-le" => integer(left)? <= integer(right)?,
+		b"-gt" => integer(left)? > integer(right)?,
+		b"-ge" => integer(left)? >= integer(right)?,
+		b"-nt" | b"-ot" | b"-ef" => {
+			let l = fs::metadata(OsStr::from_bytes(left)).ok();
+			let r = fs::metadata(OsStr::from_bytes(right)).ok();
+			match (op, l, r) {
+				(b"-nt", Some(l), Some(r)) => (l.mtime(), l.mtime_nsec()) > (r.mtime(), r.mtime_nsec()),
+				(b"-nt", l, _) => l.is_some(),
+				(b"-ot", Some(l), Some(r)) => (l.mtime(), l.mtime_nsec()) < (r.mtime(), r.mtime_nsec()),
+				(b"-ot", _, r) => r.is_some(),
+				(_, Some(l), Some(r)) => l.dev() == r.dev() && l.ino() == r.ino(),
+				_ => false,
+			}
+		},
+		_ => { return Err(format!("{}: binary operator expected", String::from_utf8_lossy(op))); },
+	})
+}
+
+/// Matches `s` against a shell pattern, in which a backslash makes the next character literal.
+pub fn pattern_match(pattern: &[u8], s: &[u8]) -> bool {
+	unsafe{ libc::fnmatch(to_cstring(pattern).as_ptr(), to_cstring(s).as_ptr(), 0) == 0 }
+}
+
+/// Matches `s` against an extended regular expression and returns the matched text and subexpressions.
+pub fn regex_match(pattern: &[u8], s: &[u8]) -> CondResult<Option<Vec<Vec<u8>>>> {
+	const MAX_GROUPS: usize = 10;
+	unsafe{
+		let mut regex: libc::regex_t = mem::zeroed();
+		if libc::regcomp(&mut regex, to_cstring(pattern).as_ptr(), libc::REG_EXTENDED) != 0 {
+			return Err(format!("{}: invalid regular expression", String::from_utf8_lossy(pattern)));
+		}
+		let mut groups: [libc::regmatch_t; MAX_GROUPS] = mem::zeroed();
+		let s = to_cstring(s);
+		let r = libc::regexec(&regex, s.as_ptr(), MAX_GROUPS, groups.as_mut_ptr(), 0);
+		libc::regfree(&mut regex);
+		if r != 0 {
+			return Ok(None);
+		}
+		let s = s.as_bytes();
+		let mut matched = vec![];
+		for m in groups.iter().take_while(|m| m.rm_so >= 0) {
+			matched.push(s[m.rm_so as usize .. m.rm_eo as usize].to_vec());
+		}
+		Ok(Some(matched))
+	}
+}
+
+/// Evaluates the arguments of `test` following the POSIX rules on the number of arguments,
+/// and falls back to `!`, `-a`, `-o` and parentheses with the usual precedence.
+pub fn test(args: &[&[u8]]) -> CondResult<bool> {
+	match args.len() {
+		0 => Ok(false),
+		1 => Ok(!args[0].is_empty()),
+		2 if args[0] == b"!" => test(&args[1 ..]).map(|b| !b),
+		2 if is_unary_operator(args[0]) => Ok(unary(args[0], args[1])),
+		2 => Err(format!("{}: unary operator expected", String::from_utf8_lossy(args[0]))),
+		3 if is_binary_operator(args[1]) => binary(args[0], args[1], args[2]),
+		3 if args[0] == b"!" => test(&args[1 ..]).map(|b| !b),
+		3 if args[0] == b"(" && args[2] == b")" => test(&args[1 .. 2]),
+		4 if args[0] == b"!" => test(&args[1 ..]).map(|b| !b),
+		4 if args[0] == b"(" && args[3] == b")" => test(&args[1 .. 3]),
+		_ => {
+			let mut parser = TestParser { args: args, i: 0 };
+			let b = parser.parse_or()?;
+			match parser.args.get(parser.i) {
+				None => Ok(b),
+				Some(arg) => Err(format!("{}: unexpected argument", String::from_utf8_lossy(arg))),
+			}
+		},
+	}
+}
+
+struct TestParser<'a, 'b: 'a> {
+	args: &'a [&'b [u8]],
+	i: usize,
+}
+
+impl<'a, 'b> TestParser<'a, 'b> {
+	fn next(&mut self) -> CondResult<&'b [u8]> {
+		match self.args.get(self.i) {
+			Some(&arg) => {
+				self.i += 1;
+				Ok(arg)
+			},
+			None => Err("argument expected".to_string()),
+		}
+	}
+
+	fn accept(&mut self, op: &[u8]) -> bool {
+		if self.args.get(self.i) == Some(&op) {
+			self.i += 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn parse_or(&mut self) -> CondResult<bool> {
+		let mut b = self.parse_and()?;
+		while self.accept(b"-o") {
+			b = self.parse_and()? || b;
+		}
+		Ok(b)
+	}
+
+	fn parse_and(&mut self) -> CondResult<bool> {
+		let mut b = self.parse_not()?;
+		while self.accept(b"-a") {
+			b = self.parse_not()? && b;
+		}
+		Ok(b)
+	}
+
+	fn parse_not(&mut self) -> CondResult<bool> {
+		if self.accept(b"!") {
+			return self.parse_not().map(|b| !b);
+		}
+		self.parse_primary()
+	}
+
+	fn parse_primary(&mut self) -> CondResult<bool> {
+		if self.accept(b"(") {
+			let b = self.parse_or()?;
+			if !self.accept(b")") {
+				return Err("')' expected".to_string());
+			}
+			return Ok(b);
+		}
+		let arg = self.next()?;
+		if is_unary_operator(arg) && self.i < self.args.len() {
+			return Ok(unary(arg, self.next()?));
+		}
+		match self.args.get(self.i) {
+			Some(&op) if is_binary_operator(op) => {
+				self.i += 1;
+				let right = self.next()?;
+				binary(arg, op, right)
+			},
+			_ => Ok(!arg.is_empty()),
+		}
+	}
+}
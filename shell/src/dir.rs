@@ -0,0 +1,103 @@
+use global;
+use var;
+
+use std::{env,fs};
+use std::ffi::OsStr;
+use std::os::unix::ffi::{OsStrExt,OsStringExt};
+use nix;
+use std::os::unix::fs::MetadataExt;
+use nix::unistd;
+
+const PWD_KEY: &'static [u8] = b"PWD";
+const OLDPWD_KEY: &'static [u8] = b"OLDPWD";
+
+/// The logical working directory, which keeps the symbolic links it was reached through,
+/// and the directory stack of `pushd` and `popd`.
+pub struct Dirs {
+	pub pwd: Vec<u8>,
+	/// The directories below the current one, top first.
+	pub stack: Vec<Vec<u8>>,
+}
+
+fn same_file(a: &[u8], b: &[u8]) -> bool {
+	match (fs::metadata(OsStr::from_bytes(a)), fs::metadata(OsStr::from_bytes(b))) {
+		(Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+		_ => false,
+	}
+}
+
+fn physical_pwd() -> nix::Result<Vec<u8>> {
+	unistd::getcwd().map(|p| p.into_os_string().into_vec())
+}
+
+impl Dirs {
+	/// Takes over `PWD` from the environment when it names the working directory.
+	pub fn new() -> Dirs {
+		let pwd = match env::var_os(OsStr::from_bytes(PWD_KEY)) {
+			Some(ref pwd) if pwd.as_bytes().starts_with(b"/") && same_file(pwd.as_bytes(), b".") => normalize(pwd.as_bytes()),
+			_ => physical_pwd().unwrap_or(vec![]),
+		};
+		Dirs { pwd: pwd, stack: vec![] }
+	}
+}
+
+/// Removes `.` and `..` components and duplicate slashes from an absolute path without looking at the file system.
+pub fn normalize(path: &[u8]) -> Vec<u8> {
+	let mut components: Vec<&[u8]> = vec![];
+	for component in path.split(|&c| c == b'/') {
+		match component {
+			b"" | b"." => {},
+			b".." => { components.pop(); },
+			_ => components.push(component),
+		}
+	}
+	let mut normalized = vec![];
+	for component in components {
+		normalized.push(b'/');
+		normalized.extend(component);
+	}
+	if normalized.is_empty() {
+		normalized.push(b'/');
+	}
+	normalized
+}
+
+/// Changes the working directory and updates `PWD` and `OLDPWD`.
+/// Unless `physical` is set, `..` removes the last component of the logical path instead of following the link.
+pub fn change_dir(state: &mut global::State, dir: &[u8], physical: bool) -> nix::Result<()> {
+	let logical = if dir.starts_with(b"/") {
+		normalize(dir)
+	} else {
+		let mut path = state.dirs.pwd.clone();
+		path.push(b'/');
+		path.extend(dir);
+		normalize(&path)
+	};
+	let pwd = if physical {
+		unistd::chdir(dir)?;
+		physical_pwd()?
+	} else {
+		unistd::chdir(&logical[..])?;
+		logical
+	};
+	let oldpwd = ::std::mem::replace(&mut state.dirs.pwd, pwd);
+	state.vars.set_scalar(OLDPWD_KEY, &oldpwd);
+	state.vars.export(OLDPWD_KEY);
+	let pwd = state.dirs.pwd.clone();
+	state.vars.set_scalar(PWD_KEY, &pwd);
+	state.vars.export(PWD_KEY);
+	Ok(())
+}
+
+/// Replaces the home directory at the start of a path with `~`.
+pub fn abbreviate_home(state: &global::State, path: &[u8]) -> Vec<u8> {
+	if let Some(&var::Value::Scalar(ref home)) = state.vars.get(b"HOME") {
+		let home = normalize(home);
+		if home != b"/" && path.starts_with(&home) && (path.len() == home.len() || path[home.len()] == b'/') {
+			let mut abbreviated = b"~".to_vec();
+			abbreviated.extend(&path[home.len() ..]);
+			return abbreviated;
+		}
+	}
+	path.to_vec()
+}
@@ -0,0 +1,125 @@
+//! The errors of the shell, and how they are reported on stderr.
+
+use parser;
+use expand;
+
+use std::{error,fmt,ffi,io};
+use std::io::Write;
+use nix;
+
+#[derive(Debug)]
+pub enum Error {
+	Parse(parser::ParseError),
+	Expand(expand::ExpandError),
+	/// The file of a redirection could not be opened. Holds the expanded target.
+	Redirect(Vec<u8>, io::Error),
+	/// A command that is neither a builtin nor found in `PATH`.
+	CommandNotFound(Vec<u8>),
+	/// `execve` failed on the path of a command.
+	Exec(Vec<u8>, nix::Error),
+	/// A command file that exists but is neither an executable nor a script.
+	NotExecutable(Vec<u8>),
+	/// A system call failed while running a command, such as `fork` or `pipe` when starting a job.
+	Sys(&'static str, nix::Error),
+	Io(io::Error),
+	Nul(ffi::NulError),
+}
+impl From<parser::ParseError> for Error {
+	fn from(e: parser::ParseError) -> Error {
+		Error::Parse(e)
+	}
+}
+impl From<expand::ExpandError> for Error {
+	fn from(e: expand::ExpandError) -> Error {
+		Error::Expand(e)
+	}
+}
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Error {
+		Error::Io(e)
+	}
+}
+impl From<ffi::NulError> for Error {
+	fn from(e: ffi::NulError) -> Error {
+		Error::Nul(e)
+	}
+}
+
+/// Describes a system error without the name of the errno constant that nix puts first.
+pub fn describe_nix(e: &nix::Error) -> String {
+	match *e {
+		nix::Error::Sys(errno) => errno.desc().to_string(),
+		_ => e.to_string(),
+	}
+}
+
+/// Describes an I/O error like `strerror`.
+pub fn describe_io(e: &io::Error) -> String {
+	match e.raw_os_error() {
+		Some(n) => nix::Errno::from_i32(n).desc().to_string(),
+		None => e.to_string(),
+	}
+}
+
+impl Error {
+	/// The exit status of a command that failed with this error.
+	pub fn status(&self) -> u8 {
+		match *self {
+			Error::Parse(..) => 2,
+			Error::CommandNotFound(..) | Error::Exec(_, nix::Error::Sys(nix::Errno::ENOENT)) => 127,
+			Error::Exec(..) | Error::NotExecutable(..) => 126,
+			Error::Expand(..) | Error::Redirect(..) | Error::Sys(..) | Error::Io(..) | Error::Nul(..) => 1,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Error::Parse(ref e) => write!(f, "syntax error: {}", e),
+			Error::Expand(ref e) => write!(f, "{}", e),
+			Error::Redirect(ref target, ref e) => write!(f, "{}: {}", String::from_utf8_lossy(target), describe_io(e)),
+			Error::CommandNotFound(ref name) => write!(f, "{}: command not found", String::from_utf8_lossy(name)),
+			Error::Exec(ref path, ref e) => write!(f, "{}: {}", String::from_utf8_lossy(path), describe_nix(e)),
+			Error::NotExecutable(ref path) => write!(f, "{}: cannot execute binary file", String::from_utf8_lossy(path)),
+			Error::Sys(what, ref e) => write!(f, "{}: {}", what, describe_nix(e)),
+			Error::Io(ref e) => write!(f, "I/O error: {}", describe_io(e)),
+			Error::Nul(_) => write!(f, "argument contains a null byte"),
+		}
+	}
+}
+impl error::Error for Error {
+	fn description(&self) -> &str {
+		match *self {
+			Error::Parse(ref e) => e.description(),
+			Error::Expand(ref e) => e.description(),
+			Error::Redirect(_, ref e) => e.description(),
+			Error::CommandNotFound(..) => "command not found",
+			Error::Exec(_, ref e) | Error::Sys(_, ref e) => e.description(),
+			Error::NotExecutable(..) => "cannot execute binary file",
+			Error::Io(ref e) => e.description(),
+			Error::Nul(ref e) => e.description(),
+		}
+	}
+	fn cause(&self) -> Option<&error::Error> {
+		match *self {
+			Error::Parse(ref e) => Some(e),
+			Error::Expand(ref e) => Some(e),
+			Error::Exec(_, ref e) | Error::Sys(_, ref e) => Some(e),
+			Error::Redirect(_, ref e) | Error::Io(ref e) => Some(e),
+			Error::Nul(ref e) => Some(e),
+			Error::CommandNotFound(..) | Error::NotExecutable(..) => None,
+		}
+	}
+}
+
+/// Writes `ish: <context>: <message>` to stderr.
+pub fn report<C: fmt::Display, M: fmt::Display>(context: C, message: M) {
+	let _ = writeln!(&mut io::stderr(), "ish: {}: {}", context, message);
+}
+
+/// Writes an error that carries its own context to stderr, and returns its exit status.
+pub fn report_error(e: &Error) -> u8 {
+	let _ = writeln!(&mut io::stderr(), "ish: {}", e);
+	e.status()
+}
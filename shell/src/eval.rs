@@ -2,120 +2,250 @@ use parser;
 use job;
 use global;
 use builtin;
+use expand;
+use var;
+use trap;
+use cond;
+use error;
 
-use std::{env,error,fmt,fs,ffi,io};
-use std::ffi::{CString,OsString,OsStr};
-use io::Write;
+use std::{fs,ffi,io};
+use std::ffi::{CString,OsStr};
+use std::os::unix::io::RawFd;
+use std::io::Write;
 use nix;
 use nix::{unistd,fcntl};
+use nix::sys::signal::Signal;
+use nix::sys::wait::{self,WaitStatus};
 use libc;
 
-#[derive(Debug)]
-enum ExecError {
-	NixError(nix::Error),
-	IoError(io::Error),
-	NulError(ffi::NulError),
-}
-impl From<nix::Error> for ExecError {
-	fn from(e: nix::Error) -> ExecError {
-		ExecError::NixError(e)
-	}
-}
-impl From<io::Error> for ExecError {
-	fn from(e: io::Error) -> ExecError {
-		ExecError::IoError(e)
-	}
+const EXEC_BUILTIN: &'static [u8] = b"exec";
+const PIPESTATUS_KEY: &'static [u8] = b"PIPESTATUS";
+const PS4_KEY: &'static [u8] = b"PS4";
+const DEFAULT_PS4: &'static [u8] = b"+ ";
+
+/// A simple command after expansion.
+struct Expanded {
+	assignments: Vec<(Vec<u8>, Vec<u8>)>,
+	argv: Vec<Vec<u8>>,
 }
-impl From<ffi::NulError> for ExecError {
-	fn from(e: ffi::NulError) -> ExecError {
-		ExecError::NulError(e)
-	}
+
+fn trace(state: &mut global::State, expanded: &Expanded) {
+	let ps4 = match state.vars.get(PS4_KEY) {
+		Some(&var::Value::Scalar(ref ps4)) => ps4.clone(),
+		_ => DEFAULT_PS4.to_vec(),
+	};
+	let mut line = expand::expand_word(state, &ps4).unwrap_or(ps4);
+	let mut words: Vec<Vec<u8>> = expanded.assignments.iter().map(|&(ref name, ref value)| {
+		let mut word = name.clone();
+		word.push(b'=');
+		word.extend(value);
+		word
+	}).collect();
+	words.extend(expanded.argv.iter().cloned());
+	line.extend(words.join(&b' '));
+	line.push(b'\n');
+	let _ = io::stderr().write(&line);
 }
-impl fmt::Display for ExecError {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		match *self {
-			ExecError::NixError(ref e) => write!(f, "Nix error: {}", e),
-			ExecError::IoError(ref e) => write!(f, "IO error: {}", e),
-			ExecError::NulError(ref e) => write!(f, "Nul char error: {}", e),
-		}
+
+fn expand_command(state: &mut global::State, command: &parser::SimpleCommand) -> Result<Expanded, expand::ExpandError> {
+	let mut assignments = Vec::with_capacity(command.assignments.len());
+	for &assignment in &command.assignments {
+		let eq = assignment.iter().position(|&c| c == b'=').unwrap();
+		assignments.push((assignment[.. eq].to_vec(), expand::expand_assignment(state, &assignment[eq + 1 ..])?));
 	}
-}
-impl error::Error for ExecError {
-	fn description(&self) -> &str {
-		match *self {
-			ExecError::NixError(ref e) => e.description(),
-			ExecError::IoError(ref e) => e.description(),
-			ExecError::NulError(ref e) => e.description(),
+	let mut argv = Vec::with_capacity(command.arguments.len() + 1);
+	let words = if command.name.is_empty() { None } else { Some(command.name) };
+	for word in words.into_iter().chain(command.arguments.iter().cloned()) {
+		for word in expand::expand_braces(word) {
+			argv.extend(expand::expand_fields(state, &word)?);
 		}
 	}
-	fn cause(&self) -> Option<&error::Error> {
-		match *self {
-			ExecError::NixError(ref e) => Some(e),
-			ExecError::IoError(ref e) => Some(e),
-			ExecError::NulError(ref e) => Some(e),
-		}
+	let expanded = Expanded { assignments: assignments, argv: argv };
+	if state.options.xtrace {
+		trace(state, &expanded);
 	}
+	Ok(expanded)
 }
 
-fn do_exec_command(state: &mut global::State, command: &parser::Command, skip_match_builtin: bool) -> Result<u8, ExecError> {
-	use std::os::unix::ffi::{OsStrExt,OsStringExt};
+fn apply_redirects(state: &mut global::State, redirects: &Vec<parser::Redirect>) -> Result<(), error::Error> {
+	use std::os::unix::ffi::OsStrExt;
 	use std::os::unix::io::IntoRawFd;
 
-	for redirect in &command.redirects {
+	for redirect in redirects {
 		let mut oopt = fs::OpenOptions::new();
 		let _ = match redirect.typ {
 			parser::RedirectType::Input => oopt.read(true),
-			parser::RedirectType::Output => oopt.write(true).create(true),
+			parser::RedirectType::Output => oopt.write(true).create(true).truncate(true),
 			parser::RedirectType::Append => oopt.append(true).create(true),
 		};
-		let file = oopt.open(OsStr::from_bytes(redirect.target))?;
+		let target = expand::expand_word(state, redirect.target)?;
+		let file = match oopt.open(OsStr::from_bytes(&target)) {
+			Ok(file) => file,
+			Err(e) => { return Err(error::Error::Redirect(target, e)); },
+		};
 		let fd = file.into_raw_fd();
-		unistd::dup2(fd, redirect.from)?;
-		unistd::close(fd)?;
+		if fd == redirect.from {
+			fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFD(fcntl::FdFlag::empty())).map_err(|e| error::Error::Sys("fcntl", e))?;
+		} else {
+			unistd::dup2(fd, redirect.from).map_err(|e| error::Error::Sys("dup2", e))?;
+			let _ = unistd::close(fd);
+		}
+	}
+	Ok(())
+}
+
+/// Saved fds start here, above the ones that scripts usually redirect.
+const SAVED_FD_MIN: RawFd = 10;
+
+/// Keeps copies of the fds that `redirects` replace, so that a command run in the shell process can have its
+/// redirections undone. `None` means that the fd was closed.
+fn save_fds(redirects: &Vec<parser::Redirect>) -> nix::Result<Vec<(RawFd, Option<RawFd>)>> {
+	let mut saved = vec![];
+	for redirect in redirects {
+		if saved.iter().any(|&(fd, _)| fd == redirect.from) {
+			continue;
+		}
+		let copy = match fcntl::fcntl(redirect.from, fcntl::FcntlArg::F_DUPFD_CLOEXEC(SAVED_FD_MIN)) {
+			Ok(copy) => Some(copy),
+			Err(nix::Error::Sys(nix::Errno::EBADF)) => None,
+			Err(e) => {
+				restore_fds(saved);
+				return Err(e);
+			},
+		};
+		saved.push((redirect.from, copy));
+	}
+	Ok(saved)
+}
+
+fn restore_fds(saved: Vec<(RawFd, Option<RawFd>)>) {
+	for (fd, copy) in saved.into_iter().rev() {
+		match copy {
+			Some(copy) => {
+				let _ = unistd::dup2(copy, fd);
+				let _ = unistd::close(copy);
+			},
+			None => { let _ = unistd::close(fd); },
+		}
+	}
+}
+
+fn redirect_failed(state: &mut global::State, e: error::Error) -> u8 {
+	match e {
+		error::Error::Expand(e) => expand_failed(state, e),
+		e => error::report_error(&e),
+	}
+}
+
+/// Runs `f` in the shell process with `redirects` applied, and puts the fds of the shell back afterwards.
+fn with_redirects<F>(state: &mut global::State, redirects: &Vec<parser::Redirect>, f: F) -> u8
+		where F: FnOnce(&mut global::State) -> u8 {
+	if redirects.is_empty() {
+		return f(state);
+	}
+	let _ = io::stdout().flush();
+	let saved = match save_fds(redirects) {
+		Ok(saved) => saved,
+		Err(e) => { return redirect_failed(state, error::Error::Sys("dup", e)); },
+	};
+	let s = match apply_redirects(state, redirects) {
+		Ok(()) => f(state),
+		Err(e) => redirect_failed(state, e),
+	};
+	let _ = io::stdout().flush();
+	restore_fds(saved);
+	s
+}
+
+fn do_exec_simple_command(state: &mut global::State, command: &parser::SimpleCommand, expanded: Option<Expanded>,
+                          skip_match_builtin: bool) -> Result<u8, error::Error> {
+	let expanded = match expanded {
+		Some(expanded) => expanded,
+		None => expand_command(state, command)?,
+	};
+	for &(ref name, ref value) in &expanded.assignments {
+		state.vars.set_scalar(name, value);
+		state.vars.export(name);
+	}
+	apply_redirects(state, &command.redirects)?;
+	let argv = expanded.argv;
+	if argv.is_empty() {
+		return Ok(0);
 	}
 	if !skip_match_builtin {
-		if let Some(builtin) = builtin::match_builtin(command.name) {
-			return Ok(builtin(state, &command.arguments));
+		if let Some(builtin) = state.builtins.get(&argv[0]) {
+			let args: Vec<&[u8]> = argv[1 ..].iter().map(|a| &a[..]).collect();
+			return Ok(builtin::run(state, &*builtin, &args));
 		}
 	}
-	let ref cmd_name = CString::new(command.name.to_owned())?;
+	exec_external(state, argv)
+}
+
+/// Replaces the process with an external command looked up in the search cache.
+/// Returns only when the command cannot be executed.
+pub fn exec_external(state: &mut global::State, argv: Vec<Vec<u8>>) -> Result<u8, error::Error> {
+	let ref cmd_name = CString::new(argv[0].clone())?;
 	let external = if cmd_name.to_bytes().iter().any(|&c| c == b'/') {
-		cmd_name
+		cmd_name.clone()
 	} else {
 		 match state.search_cache.lookup(&cmd_name) {
-			Some(e) => e,
-			None => {
-				let mut stderr = io::stderr();
-				let _ = stderr.write(b"command not found: ");
-				let _ = stderr.write(command.name);
-				let _ = stderr.write(b"\n");
-				let _ = stderr.flush();
-				return Ok(127);
-			}
+			Some(e) => e.clone(),
+			None => { return Err(error::Error::CommandNotFound(argv[0].clone())); },
 		}
 	};
-	let argv: Result<Vec<CString>, ffi::NulError> = command.arguments.iter().map(|&s| CString::new(s)).collect();
-	let mut argv: Vec<CString> = argv?;
-	argv.insert(0, CString::new(command.name)?);
-	let envp: Result<Vec<CString>, ffi::NulError> = env::vars_os().map(|(mut k, v)| CString::new({ k.push(OsString::from("=")); k.push(v); k.into_vec() })).collect();
+	let argv: Result<Vec<CString>, ffi::NulError> = argv.into_iter().map(CString::new).collect();
+	let argv: Vec<CString> = argv?;
+	let envp: Result<Vec<CString>, ffi::NulError> = state.vars.environment().into_iter().map(CString::new).collect();
 	let envp: Vec<CString> = envp?;
-	unistd::execve(external, &argv, &envp)?;
-	unreachable!()
+	let e = match unistd::execve(&external, &argv, &envp) {
+		Ok(_) => unreachable!(),
+		Err(e) => e,
+	};
+	match e {
+		nix::Error::Sys(nix::Errno::ENOEXEC) => exec_script(state, &external),
+		_ => Err(error::Error::Exec(argv[0].to_bytes().to_vec(), e)),
+	}
 }
 
-fn exec_command(state: &mut global::State, command: &parser::Command, skip_match_builtin: bool) -> ! {
-	use std::error::Error;
+fn do_exec_command(state: &mut global::State, command: &parser::Command, expanded: Option<Expanded>,
+                   skip_match_builtin: bool, exempt: bool) -> Result<u8, error::Error> {
+	match *command {
+		parser::Command::Simple(ref simple) => do_exec_simple_command(state, simple, expanded, skip_match_builtin),
+		parser::Command::Compound(ref compound, ref redirects) => {
+			apply_redirects(state, redirects)?;
+			Ok(eval_compound(state, compound, exempt))
+		},
+	}
+}
 
-	let r = do_exec_command(state, command, skip_match_builtin);
-	let s = r.unwrap_or_else(|e| {
-		let _ = writeln!(&mut io::stderr(), "{}", e.description());
-		126
-	});
-	unsafe{ libc::_exit(s as libc::c_int) }
+fn exec_script(state: &mut global::State, path: &CString) -> Result<u8, error::Error> {
+	use std::os::unix::ffi::OsStrExt;
+	use std::io::Read;
+
+	let mut script: Vec<u8> = vec![];
+	fs::File::open(OsStr::from_bytes(path.to_bytes()))?.read_to_end(&mut script)?;
+	let first_line = script.split(|&c| c == b'\n').next().unwrap_or(&[]);
+	if first_line.iter().any(|&c| c == b'\0') {
+		return Err(error::Error::NotExecutable(path.to_bytes().to_vec()));
+	}
+	// The jobs of the parent shell are not our children.
+	state.job_set = job::JobSet::new();
+	let s = eval_file(state, &script, path.to_bytes());
+	request_exit(state, s);
+	Ok(s)
 }
 
-fn spawn_commands(state: &mut global::State, pipeline: &parser::Pipeline, skip_match_builtin: bool,
-                  job_builder: &mut job::JobBuilder) -> nix::Result<()> {
+fn exec_command(state: &mut global::State, command: &parser::Command, expanded: Option<Expanded>,
+                skip_match_builtin: bool, exempt: bool) -> ! {
+	let r = do_exec_command(state, command, expanded, skip_match_builtin, exempt);
+	let s = r.unwrap_or_else(|e| error::report_error(&e));
+	exit_subshell(state, s)
+}
+
+/// `exempt` is passed on to compound commands, which ignore errexit in the same places as the pipeline.
+fn spawn_commands(state: &mut global::State, pipeline: &parser::Pipeline, mut expanded: Option<Expanded>,
+                  skip_match_builtin: bool, is_background: bool, exempt: bool,
+                  job_builder: &mut job::JobBuilder) -> Result<(), error::Error> {
 	let mut pipe_stdin = 0;
 	let mut pipe_stdout = 0;
 	let mut pipe_stdout_next = 0;
@@ -123,27 +253,37 @@ fn spawn_commands(state: &mut global::State, pipeline: &parser::Pipeline, skip_m
 	for i in (0 .. pipeline.commands.len()).rev() {
 		let is_first = i == 0;
 		if !is_first {
-			let (pipe_read, pipe_write) = unistd::pipe2(fcntl::O_CLOEXEC)?;
+			let (pipe_read, pipe_write) = unistd::pipe2(fcntl::O_CLOEXEC).map_err(|e| error::Error::Sys("pipe", e))?;
 			pipe_stdin = pipe_read;
 			pipe_stdout_next = pipe_write;
 		}
-		match job_builder.push_fork(pipeline.is_background)? {
+		match job_builder.push_fork(pipeline.command_texts[i], is_background).map_err(|e| error::Error::Sys("fork", e))? {
 			unistd::ForkResult::Parent{..} => {
 				if !is_last {
-					unistd::close(pipe_stdout)?;
+					let _ = unistd::close(pipe_stdout);
 				}
 				if !is_first {
-					unistd::close(pipe_stdin)?;
+					let _ = unistd::close(pipe_stdin);
 				}
 			},
 			unistd::ForkResult::Child => {
+				// A builtin or compound command never execs, so the pipe ends must not stay open here.
 				if !is_last {
-					unistd::dup2(pipe_stdout, libc::STDOUT_FILENO)?;
+					unistd::dup2(pipe_stdout, libc::STDOUT_FILENO).map_err(|e| error::Error::Sys("dup2", e))?;
+					let _ = unistd::close(pipe_stdout);
 				}
 				if !is_first {
-					unistd::dup2(pipe_stdin, libc::STDIN_FILENO)?;
+					unistd::dup2(pipe_stdin, libc::STDIN_FILENO).map_err(|e| error::Error::Sys("dup2", e))?;
+					let _ = unistd::close(pipe_stdin);
+					let _ = unistd::close(pipe_stdout_next);
+				}
+				state.traps.reset_for_subshell();
+				// Subshells have no job control. A simple command keeps the list of jobs for `jobs | ...`.
+				match pipeline.commands[i] {
+					parser::Command::Simple(_) => state.job_set.set_terminal(None),
+					parser::Command::Compound(..) => { state.job_set = job::JobSet::new(); },
 				}
-				exec_command(state, &pipeline.commands[i], skip_match_builtin);
+				exec_command(state, &pipeline.commands[i], expanded.take(), skip_match_builtin, exempt);
 			},
 		}
 		pipe_stdout = pipe_stdout_next;
@@ -152,48 +292,528 @@ fn spawn_commands(state: &mut global::State, pipeline: &parser::Pipeline, skip_m
 	Ok(())
 }
 
-pub enum EvalResult<'a> {
+enum EvalResult<'a> {
 	Done(u8),
 	Running(job::JobDescriptor<'a>),
 }
 
-fn eval_pipeline<'a>(state: &'a mut global::State, pipeline: &parser::Pipeline) -> EvalResult<'a> {
+/// Reports an expansion error, which makes a non-interactive shell exit.
+fn expand_failed(state: &mut global::State, e: expand::ExpandError) -> u8 {
+	let s = error::report_error(&error::Error::Expand(e));
+	if !state.options.interactive {
+		request_exit(state, s);
+	}
+	s
+}
+
+fn run_simple_command(state: &mut global::State, command: &parser::SimpleCommand) -> Result<u8, Expanded> {
+	state.substitution_status = None;
+	let expanded = match expand_command(state, command) {
+		Ok(expanded) => expanded,
+		Err(e) => { return Ok(expand_failed(state, e)); },
+	};
+	if expanded.argv.is_empty() {
+		for &(ref name, ref value) in &expanded.assignments {
+			state.vars.set_scalar(name, value);
+		}
+		// Without a command, the status is that of the last command substitution.
+		let s = state.substitution_status.unwrap_or(0);
+		return Ok(with_redirects(state, &command.redirects, |_| s));
+	}
+	// `exec` without a command makes its redirections permanent.
+	if expanded.argv.len() == 1 && &expanded.argv[0][..] == EXEC_BUILTIN {
+		return Ok(match apply_redirects(state, &command.redirects) {
+			Ok(()) => 0,
+			Err(e) => redirect_failed(state, e),
+		});
+	}
+	if let Some(builtin) = state.builtins.get(&expanded.argv[0]) {
+		let saved: Vec<_> = expanded.assignments.iter().map(|&(ref name, _)| state.vars.save(name)).collect();
+		for &(ref name, ref value) in &expanded.assignments {
+			state.vars.set_scalar(name, value);
+		}
+		let args: Vec<&[u8]> = expanded.argv[1 ..].iter().map(|a| &a[..]).collect();
+		let s = with_redirects(state, &command.redirects, |state| builtin::run(state, &*builtin, &args));
+		for (&(ref name, _), saved) in expanded.assignments.iter().zip(saved).rev() {
+			state.vars.restore(name, saved);
+		}
+		return Ok(s);
+	}
+	Err(expanded)
+}
+
+fn run_pipeline<'a>(state: &'a mut global::State, pipeline: &parser::Pipeline, is_background: bool, exempt: bool) -> EvalResult<'a> {
 	let commands = &pipeline.commands;
 	assert!(commands.len() > 0);
 
 	let mut skip_match_builtin = false;
-	if commands.len() == 1 && commands[0].redirects.is_empty() {
-		if let Some(func) = builtin::match_builtin(commands[0].name) {
-			let s = func(state, &commands[0].arguments);
-			return EvalResult::Done(s);
+	let mut expanded = None;
+	if commands.len() == 1 && !is_background {
+		match commands[0] {
+			parser::Command::Simple(ref command) => match run_simple_command(state, command) {
+				Ok(s) => { return EvalResult::Done(s); },
+				Err(e) => {
+					skip_match_builtin = true;
+					expanded = Some(e);
+				},
+			},
+			parser::Command::Compound(ref compound, ref redirects) => {
+				return EvalResult::Done(with_redirects(state, redirects, |state| eval_compound(state, compound, exempt)));
+			},
 		}
-		skip_match_builtin = true;
 	}
 
-	let mut job_builder = job::JobBuilder::new(commands.len());
-	if let Err(e) = spawn_commands(state, pipeline, skip_match_builtin, &mut job_builder) {
-		use std::error::Error;
-		let _ = writeln!(&mut io::stderr(), "{}", e.description());
-	}
+	let mut job_builder = job::JobBuilder::new(pipeline.text, commands.len(), state.job_set.terminal());
+	let r = spawn_commands(state, pipeline, expanded, skip_match_builtin, is_background, exempt, &mut job_builder);
+	let s = r.err().map_or(1, |e| error::report_error(&e));
 	if job_builder.is_empty() {
-		EvalResult::Done(126)
+		EvalResult::Done(s)
 	} else {
 		EvalResult::Running(state.job_set.push(job_builder.build()))
 	}
 }
 
-pub fn eval<'a>(state: &'a mut global::State, pipeline: &parser::Pipeline) -> EvalResult<'a> {
+fn report_signaled(status: WaitStatus) {
+	let mut stderr = io::stderr();
+	match status {
+		// Interrupts were typed by the user, and broken pipes are routine in pipelines.
+		WaitStatus::Signaled(_, Signal::SIGINT, _) => { let _ = stderr.write(b"\n"); },
+		WaitStatus::Signaled(_, Signal::SIGPIPE, _) => {},
+		WaitStatus::Signaled(_, sig, core_dumped) => {
+			let _ = writeln!(&mut stderr, "{}{}", job::signal_description(sig), if core_dumped { " (core dumped)" } else { "" });
+		},
+		_ => {},
+	}
+}
+
+/// Waits for a job in the foreground, and returns the statuses of its processes.
+pub fn wait_foreground(job_desc: &mut job::JobDescriptor, shell_modes: Option<job::TerminalModes>) -> Vec<u8> {
+	use job::WaitStatusExt;
+	job_desc.wait_foreground(shell_modes);
+	let proccesses = &job_desc.job().proccesses;
+	report_signaled(proccesses.last().unwrap().status);
+	proccesses.iter().map(|pr| pr.status.code()).collect()
+}
+
+/// `exempt` is set where errexit is ignored: in conditions, and in all but the last pipeline of an and-or list.
+fn eval_pipeline(state: &mut global::State, pipeline: &parser::Pipeline, exempt: bool) -> u8 {
+	let substitutions = state.substitution_fds.len();
+	let modes = state.terminal_modes;
+	let pipestatus: Vec<u8> = match run_pipeline(state, pipeline, false, exempt || pipeline.is_negated) {
+		EvalResult::Done(s) => vec![s],
+		EvalResult::Running(mut job_desc) => wait_foreground(&mut job_desc, modes),
+	};
+	close_process_substitutions(state, substitutions);
+	let mut s = if state.options.pipefail {
+		pipestatus.iter().rev().find(|&&s| s != 0).cloned().unwrap_or(0)
+	} else {
+		*pipestatus.last().unwrap()
+	};
+	if pipeline.is_negated {
+		s = if s == 0 { 1 } else { 0 };
+	}
+	state.vars.set(PIPESTATUS_KEY, var::Value::Array(pipestatus.iter().map(|s| s.to_string().into_bytes()).collect()));
+	state.last_status = s;
+	run_pending_traps(state);
+	if s != 0 && !exempt && !pipeline.is_negated && state.exiting.is_none() {
+		run_err_trap(state);
+		if state.options.errexit && state.exiting.is_none() {
+			request_exit(state, s);
+		}
+	}
+	s
+}
+
+/// Prints the number and process group of a job started in the background, when the shell is interactive.
+fn report_background(interactive: bool, job_desc: &job::JobDescriptor) {
+	if interactive {
+		let _ = writeln!(&mut io::stderr(), "[{}] {}", job_desc.id(), job_desc.job().gid);
+	}
+}
+
+fn eval_background(state: &mut global::State, and_or: &parser::AndOr) -> u8 {
+	let interactive = state.options.interactive;
+	if and_or.rest.is_empty() {
+		if let EvalResult::Running(job_desc) = run_pipeline(state, &and_or.first, true, false) {
+			report_background(interactive, &job_desc);
+		}
+	} else {
+		let mut job_builder = job::JobBuilder::new(and_or.text, 1, state.job_set.terminal());
+		match job_builder.push_fork(and_or.text, true) {
+			Ok(unistd::ForkResult::Parent{..}) => { report_background(interactive, &state.job_set.push(job_builder.build())); },
+			Ok(unistd::ForkResult::Child) => {
+				state.traps.reset_for_subshell();
+				state.job_set = job::JobSet::new();
+				let s = eval_and_or_foreground(state, and_or, false);
+				exit_subshell(state, s)
+			},
+			Err(e) => { error::report_error(&error::Error::Sys("fork", e)); },
+		}
+	}
+	state.last_status = 0;
+	0
+}
+
+fn eval_and_or_foreground(state: &mut global::State, and_or: &parser::AndOr, exempt: bool) -> u8 {
+	let mut s = eval_pipeline(state, &and_or.first, exempt || !and_or.rest.is_empty());
+	for (i, &(connector, ref pipeline)) in and_or.rest.iter().enumerate() {
+		if state.exiting.is_some() {
+			break;
+		}
+		match connector {
+			parser::Connector::And => if s != 0 { continue; },
+			parser::Connector::Or => if s == 0 { continue; },
+		}
+		s = eval_pipeline(state, pipeline, exempt || i + 1 != and_or.rest.len());
+	}
+	s
+}
+
+fn eval_and_or(state: &mut global::State, and_or: &parser::AndOr, exempt: bool) -> u8 {
+	if and_or.is_background {
+		eval_background(state, and_or)
+	} else {
+		eval_and_or_foreground(state, and_or, exempt)
+	}
+}
+
+fn eval_list(state: &mut global::State, list: &parser::List, exempt: bool) -> u8 {
+	let mut s = state.last_status;
+	for and_or in &list.items {
+		s = eval_and_or(state, and_or, exempt);
+		if state.exiting.is_some() {
+			break;
+		}
+	}
+	s
+}
+
+fn eval_compound(state: &mut global::State, compound: &parser::CompoundCommand, exempt: bool) -> u8 {
+	match *compound {
+		parser::CompoundCommand::BraceGroup(ref list) => eval_list(state, list, exempt),
+		parser::CompoundCommand::If { ref conditions, ref otherwise } => {
+			for &(ref condition, ref body) in conditions {
+				let s = eval_list(state, condition, true);
+				if state.exiting.is_some() {
+					return s;
+				}
+				if s == 0 {
+					return eval_list(state, body, exempt);
+				}
+			}
+			match *otherwise {
+				Some(ref body) => eval_list(state, body, exempt),
+				None => 0,
+			}
+		},
+		parser::CompoundCommand::While { is_until, ref condition, ref body } => {
+			let mut s = 0;
+			loop {
+				let c = eval_list(state, condition, true);
+				if state.exiting.is_some() {
+					return c;
+				}
+				if (c == 0) == is_until {
+					return s;
+				}
+				s = eval_list(state, body, exempt);
+				if state.exiting.is_some() {
+					return s;
+				}
+			}
+		},
+		parser::CompoundCommand::Arithmetic(expr) => {
+			let result = expand::expand_word(state, expr)
+				.and_then(|expr| expand::eval_arithmetic(state, &expr));
+			match result {
+				Ok(n) => if n != 0 { 0 } else { 1 },
+				Err(e) => {
+					error::report("((", e);
+					1
+				},
+			}
+		},
+		parser::CompoundCommand::Conditional(ref expr) => match eval_conditional(state, expr) {
+			Ok(true) => 0,
+			Ok(false) => 1,
+			Err(e) => {
+				error::report("[[", e);
+				2
+			},
+		},
+	}
+}
+
+const BASH_REMATCH_KEY: &'static [u8] = b"BASH_REMATCH";
+
+fn eval_conditional(state: &mut global::State, expr: &parser::CondExpr) -> cond::CondResult<bool> {
+	let expand = |state: &mut global::State, word| expand::expand_word(state, word).map_err(|e| e.to_string());
+	match *expr {
+		parser::CondExpr::Word(word) => Ok(!expand(state, word)?.is_empty()),
+		parser::CondExpr::Unary(op, word) => Ok(cond::unary(op, &expand(state, word)?)),
+		parser::CondExpr::Binary(left, op, right) => {
+			let left = expand(state, left)?;
+			match op {
+				b"=" | b"==" | b"!=" => {
+					let pattern = expand::expand_pattern(state, right, expand::GLOB_SPECIALS).map_err(|e| e.to_string())?;
+					Ok(cond::pattern_match(&pattern, &left) == (op != b"!="))
+				},
+				b"=~" => {
+					let pattern = expand::expand_pattern(state, right, expand::REGEX_SPECIALS).map_err(|e| e.to_string())?;
+					match cond::regex_match(&pattern, &left)? {
+						Some(groups) => {
+							state.vars.set(BASH_REMATCH_KEY, var::Value::Array(groups));
+							Ok(true)
+						},
+						None => {
+							state.vars.set(BASH_REMATCH_KEY, var::Value::Array(vec![]));
+							Ok(false)
+						},
+					}
+				},
+				_ => cond::binary(&left, op, &expand(state, right)?),
+			}
+		},
+		parser::CondExpr::Not(ref expr) => eval_conditional(state, expr).map(|b| !b),
+		parser::CondExpr::And(ref left, ref right) => Ok(eval_conditional(state, left)? && eval_conditional(state, right)?),
+		parser::CondExpr::Or(ref left, ref right) => Ok(eval_conditional(state, left)? || eval_conditional(state, right)?),
+	}
+}
+
+fn run_trap(state: &mut global::State, action: &[u8]) {
+	let s = state.last_status;
+	match parser::parse(action) {
+		Ok(list) => { eval(state, &list); },
+		Err(e) => { error::report("trap", error::Error::Parse(e)); },
+	}
+	state.last_status = s;
+}
+
+fn run_err_trap(state: &mut global::State) {
+	if state.traps.in_err_trap {
+		return;
+	}
+	if let Some(action) = state.traps.get(trap::Condition::Err).cloned() {
+		state.traps.in_err_trap = true;
+		run_trap(state, &action);
+		state.traps.in_err_trap = false;
+	}
+}
+
+pub fn run_pending_traps(state: &mut global::State) {
+	for cond in trap::take_pending() {
+		if let Some(action) = state.traps.get(cond).cloned() {
+			run_trap(state, &action);
+		}
+	}
+}
+
+/// Runs the command of `$(...)` in a subshell and returns its output without trailing newlines.
+pub fn command_substitution(state: &mut global::State, script: &[u8]) -> Vec<u8> {
+	use std::os::unix::io::FromRawFd;
+	use std::io::Read;
 	use job::WaitStatusExt;
-	match eval_pipeline(state, pipeline) {
-		EvalResult::Done(s) => EvalResult::Done(s),
-		EvalResult::Running(mut job_desc) => {
-			if pipeline.is_background {
-				EvalResult::Running(job_desc)
-			} else {
-				job_desc.wait();
-				let _ = job::tcsetpgrp(1, unistd::getpid());
-				EvalResult::Done(job_desc.job().proccesses.last().unwrap().status.code())
+
+	let _ = io::stdout().flush();
+	let (pipe_read, pipe_write) = match unistd::pipe2(fcntl::O_CLOEXEC) {
+		Ok(pipe) => pipe,
+		Err(e) => {
+			error::report_error(&error::Error::Sys("pipe", e));
+			return vec![];
+		},
+	};
+	match unistd::fork() {
+		Ok(unistd::ForkResult::Parent{ child }) => {
+			let _ = unistd::close(pipe_write);
+			let mut output = vec![];
+			let _ = unsafe{ fs::File::from_raw_fd(pipe_read) }.read_to_end(&mut output);
+			let status = loop {
+				match wait::waitpid(child, None) {
+					Err(nix::Error::Sys(nix::Errno::EINTR)) => {},
+					r => { break r; },
+				}
+			};
+			state.substitution_status = Some(status.map(|s| s.code()).unwrap_or(126));
+			while output.last() == Some(&b'\n') {
+				output.pop();
 			}
+			output
+		},
+		Ok(unistd::ForkResult::Child) => {
+			let _ = unistd::dup2(pipe_write, libc::STDOUT_FILENO);
+			let _ = unistd::close(pipe_write);
+			let _ = unistd::close(pipe_read);
+			trap::reset_dispositions();
+			state.traps.reset_for_subshell();
+			state.job_set = job::JobSet::new();
+			state.options.interactive = false;
+			let s = eval_script(state, script);
+			exit_subshell(state, s)
 		},
+		Err(e) => {
+			let _ = unistd::close(pipe_read);
+			let _ = unistd::close(pipe_write);
+			error::report_error(&error::Error::Sys("fork", e));
+			vec![]
+		},
+	}
+}
+
+/// Runs the command of `<(...)`, or of `>(...)` unless `is_input`, as a background job connected to a pipe,
+/// and returns the shell's end of the pipe, which stays open across `exec` until the command is done.
+pub fn process_substitution(state: &mut global::State, script: &[u8], is_input: bool) -> Option<RawFd> {
+	let _ = io::stdout().flush();
+	let (pipe_read, pipe_write) = match unistd::pipe2(fcntl::O_CLOEXEC) {
+		Ok(pipe) => pipe,
+		Err(e) => {
+			error::report_error(&error::Error::Sys("pipe", e));
+			return None;
+		},
+	};
+	let (fd, child_fd, target) = if is_input {
+		(pipe_read, pipe_write, libc::STDOUT_FILENO)
+	} else {
+		(pipe_write, pipe_read, libc::STDIN_FILENO)
+	};
+	let text = [if is_input { b"<(" } else { b">(" }, script, b")"].concat();
+	let mut job_builder = job::JobBuilder::new(&text, 1, state.job_set.terminal());
+	match job_builder.push_fork(&text, true) {
+		Ok(unistd::ForkResult::Parent{..}) => {
+			let _ = unistd::close(child_fd);
+			state.job_set.push(job_builder.build_internal());
+			if let Err(e) = fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFD(fcntl::FdFlag::empty())) {
+				error::report_error(&error::Error::Sys("fcntl", e));
+			}
+			state.substitution_fds.push(fd);
+			Some(fd)
+		},
+		Ok(unistd::ForkResult::Child) => {
+			let _ = unistd::dup2(child_fd, target);
+			let _ = unistd::close(child_fd);
+			let _ = unistd::close(fd);
+			for fd in state.substitution_fds.drain(..) {
+				let _ = unistd::close(fd);
+			}
+			state.traps.reset_for_subshell();
+			state.job_set = job::JobSet::new();
+			state.options.interactive = false;
+			let s = eval_script(state, script);
+			exit_subshell(state, s)
+		},
+		Err(e) => {
+			let _ = unistd::close(pipe_read);
+			let _ = unistd::close(pipe_write);
+			error::report_error(&error::Error::Sys("fork", e));
+			None
+		},
+	}
+}
+
+/// Reports the jobs that have stopped or are done since the last call, as an interactive shell does before a prompt.
+pub fn notify_jobs(state: &mut global::State) {
+	state.job_set.reap();
+	let mut stderr = io::stderr();
+	for event in state.job_set.take_events() {
+		let _ = writeln!(&mut stderr, "{}", event.describe());
+	}
+}
+
+/// Closes the pipes of the process substitutions made since `start`, and reaps the jobs that are done.
+fn close_process_substitutions(state: &mut global::State, start: usize) {
+	if state.substitution_fds.len() > start {
+		for fd in state.substitution_fds.drain(start ..) {
+			let _ = unistd::close(fd);
+		}
+		state.job_set.reap();
+	}
+}
+
+/// How running commands ended: by running out of commands, or by `exit`, errexit or an expansion error
+/// asking the shell to exit with a status. The shell only exits when whoever runs it decides so.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+	Done(u8),
+	Exit(u8),
+}
+
+impl Outcome {
+	/// The status of the last command, or the one to exit with.
+	pub fn status(&self) -> u8 {
+		match *self {
+			Outcome::Done(s) | Outcome::Exit(s) => s,
+		}
+	}
+}
+
+/// Makes the commands being run stop, so that the shell can exit with `s` once they have unwound.
+pub fn request_exit(state: &mut global::State, s: u8) {
+	state.exiting = Some(s);
+}
+
+/// Turns the status of commands that have finished running into an `Outcome`, clearing a pending exit.
+pub fn take_outcome(state: &mut global::State, s: u8) -> Outcome {
+	match state.exiting.take() {
+		Some(s) => Outcome::Exit(s),
+		None => Outcome::Done(s),
+	}
+}
+
+/// Runs the EXIT trap, as the shell does right before it exits, and returns the status to exit with,
+/// which the trap can change with `exit`.
+pub fn run_exit_trap(state: &mut global::State, s: u8) -> u8 {
+	state.exiting = None;
+	if let Some(action) = state.traps.get(trap::Condition::Exit).cloned() {
+		let _ = state.traps.set(trap::Condition::Exit, None);
+		state.last_status = s;
+		run_trap(state, &action);
+	}
+	let _ = io::stdout().flush();
+	state.exiting.take().unwrap_or(s)
+}
+
+/// Ends a forked subshell after running its EXIT trap. `_exit` leaves alone what the parent process set up
+/// to run at exit, such as the buffers of a program that embeds the shell.
+fn exit_subshell(state: &mut global::State, s: u8) -> ! {
+	let s = run_exit_trap(state, s);
+	unsafe{ libc::_exit(s as libc::c_int) }
+}
+
+pub fn eval(state: &mut global::State, list: &parser::List) -> u8 {
+	eval_list(state, list, false)
+}
+
+pub fn eval_script(state: &mut global::State, script: &[u8]) -> u8 {
+	eval_source(state, script, None)
+}
+
+/// Runs a script read from `path`, whose parse errors are reported with the file name and line number.
+pub fn eval_file(state: &mut global::State, script: &[u8], path: &[u8]) -> u8 {
+	eval_source(state, script, Some(path))
+}
+
+fn eval_source(state: &mut global::State, script: &[u8], path: Option<&[u8]>) -> u8 {
+	let mut i = 0;
+	while i < script.len() {
+		let list = match parser::parse_next(script, i) {
+			Ok((list, next)) => {
+				i = next;
+				list
+			},
+			Err(e) => {
+				let (line, caret) = (e.line_number(script), e.caret(script));
+				let e = error::Error::Parse(e);
+				match path {
+					Some(path) => error::report(format_args!("{}:{}", String::from_utf8_lossy(path), line), &e),
+					None => { error::report_error(&e); },
+				}
+				let _ = writeln!(&mut io::stderr(), "{}", caret);
+				return e.status();
+			},
+		};
+		eval(state, &list);
+		if state.exiting.is_some() {
+			break;
+		}
 	}
+	state.exiting.unwrap_or(state.last_status)
 }
@@ -0,0 +1,723 @@
+use global;
+use var;
+use arith;
+use eval;
+
+use std::{error,fmt};
+use std::ffi::{CStr,CString};
+use nix::unistd;
+use libc;
+
+#[derive(Debug)]
+pub enum ExpandError {
+	BadSubstitution(Vec<u8>),
+	Unbound(Vec<u8>),
+	/// An arithmetic expression that failed to evaluate, after expansion.
+	Arithmetic(Vec<u8>, arith::ArithError),
+}
+impl fmt::Display for ExpandError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ExpandError::BadSubstitution(ref s) => write!(f, "{}: bad substitution", String::from_utf8_lossy(s)),
+			ExpandError::Unbound(ref s) => write!(f, "{}: unbound variable", String::from_utf8_lossy(s)),
+			ExpandError::Arithmetic(ref expr, ref e) => write!(f, "{}: {}", String::from_utf8_lossy(expr), e),
+		}
+	}
+}
+impl error::Error for ExpandError {
+	fn description(&self) -> &str {
+		match *self {
+			ExpandError::BadSubstitution(..) => "bad substitution",
+			ExpandError::Unbound(..) => "unbound variable",
+			ExpandError::Arithmetic(_, ref e) => e.description(),
+		}
+	}
+}
+
+pub type ExpandResult<T> = Result<T, ExpandError>;
+
+fn is_name_start(c: u8) -> bool {
+	c == b'_' || (b'a' <= c && c <= b'z') || (b'A' <= c && c <= b'Z')
+}
+
+fn is_name_char(c: u8) -> bool {
+	is_name_start(c) || (b'0' <= c && c <= b'9')
+}
+
+fn is_special(s: &[u8]) -> bool {
+	match s {
+		b"?" | b"$" | b"-" | b"#" | b"@" | b"*" => true,
+		_ => s.len() == 1 && is_digit(s[0]),
+	}
+}
+
+fn is_digit(c: u8) -> bool {
+	b'0' <= c && c <= b'9'
+}
+
+pub fn is_name(s: &[u8]) -> bool {
+	match s.split_first() {
+		Some((&c, rest)) => is_name_start(c) && rest.iter().all(|&c| is_name_char(c)),
+		None => false,
+	}
+}
+
+/// Returns the index of the parenthesis closing the one at `open`, skipping quoted parts.
+fn find_close_paren(s: &[u8], open: usize) -> Option<usize> {
+	let mut depth = 0;
+	let mut i = open;
+	while i < s.len() {
+		match s[i] {
+			b'\\' => { i += 1; },
+			b'\'' => { i += s[i + 1 ..].iter().position(|&c| c == b'\'')? + 1; },
+			b'"' => {
+				i += 1;
+				while i < s.len() && s[i] != b'"' {
+					if s[i] == b'\\' { i += 1; }
+					i += 1;
+				}
+			},
+			b'(' => { depth += 1; },
+			b')' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(i);
+				}
+			},
+			_ => {},
+		}
+		i += 1;
+	}
+	None
+}
+
+/// The value of a parameter. `$@`, `$*` and arrays indexed with `@` or `*` have a value per element,
+/// and `*` joins the elements when quoted.
+enum Value {
+	Scalar(Vec<u8>),
+	List(Vec<Vec<u8>>, bool),
+}
+
+struct Expander<'a> {
+	state: &'a mut global::State,
+	word: &'a [u8],
+	i: usize,
+	out: Vec<u8>,
+	/// Whether each byte of `out` comes from an unquoted expansion and is therefore subject to field splitting.
+	splittable: Vec<bool>,
+	/// Whether `out` has quoted parts, which make it a field even when it is empty.
+	is_quoted_field: bool,
+	/// The fields before `out`, which are separated by the elements of `"$@"`.
+	fields: Vec<(Vec<u8>, Vec<bool>, bool)>,
+	/// Characters that are escaped with a backslash when they come from quoted text, see `expand_pattern`.
+	specials: &'static [u8],
+	quoted: bool,
+	/// Whether a `$@` was expanded in the current double quotes, so that `"$@"` without parameters is no field.
+	list_expanded: bool,
+	/// Whether the word is the value of an assignment, where tildes are also expanded after `:`.
+	is_assignment: bool,
+}
+
+impl<'a> Expander<'a> {
+	fn new(state: &'a mut global::State, word: &'a [u8], specials: &'static [u8]) -> Expander<'a> {
+		Expander { state: state, word: word, i: 0, out: Vec::with_capacity(word.len()), splittable: vec![], is_quoted_field: false,
+		           fields: vec![], specials: specials, quoted: false, list_expanded: false, is_assignment: false }
+	}
+
+	fn lookup(&self, name: &[u8], index: Option<&[u8]>) -> Option<Value> {
+		let positional = &self.state.args[1 ..];
+		match name {
+			b"?" => return Some(Value::Scalar(self.state.last_status.to_string().into_bytes())),
+			b"$" => return Some(Value::Scalar(unistd::getpid().to_string().into_bytes())),
+			b"-" => return Some(Value::Scalar(self.state.options.letters())),
+			b"#" => return Some(Value::Scalar(positional.len().to_string().into_bytes())),
+			b"@" => return Some(Value::List(positional.to_vec(), false)),
+			b"*" => return Some(Value::List(positional.to_vec(), true)),
+			_ if name.iter().all(|&c| is_digit(c)) => {
+				let n: usize = String::from_utf8_lossy(name).parse().ok()?;
+				return self.state.args.get(n).cloned().map(Value::Scalar);
+			},
+			_ => {},
+		}
+		let value = self.state.vars.get(name)?;
+		match (value, index) {
+			(&var::Value::Scalar(ref s), None) => Some(Value::Scalar(s.clone())),
+			(&var::Value::Scalar(ref s), Some(b"0")) => Some(Value::Scalar(s.clone())),
+			(&var::Value::Scalar(ref s), Some(b"@")) => Some(Value::List(vec![s.clone()], false)),
+			(&var::Value::Scalar(ref s), Some(b"*")) => Some(Value::List(vec![s.clone()], true)),
+			(&var::Value::Scalar(_), Some(_)) => None,
+			(&var::Value::Array(ref a), None) => a.get(0).cloned().map(Value::Scalar),
+			(&var::Value::Array(ref a), Some(b"@")) => Some(Value::List(a.clone(), false)),
+			(&var::Value::Array(ref a), Some(b"*")) => Some(Value::List(a.clone(), true)),
+			(&var::Value::Array(ref a), Some(i)) => {
+				let i: usize = String::from_utf8_lossy(i).parse().ok()?;
+				a.get(i).cloned().map(Value::Scalar)
+			},
+		}
+	}
+
+	fn push_quoted(&mut self, s: &[u8]) {
+		for &c in s {
+			if self.specials.contains(&c) {
+				self.out.push(b'\\');
+				self.splittable.push(false);
+			}
+			self.out.push(c);
+			self.splittable.push(false);
+		}
+		self.is_quoted_field = true;
+	}
+
+	fn push_unquoted(&mut self, c: u8) {
+		self.out.push(c);
+		self.splittable.push(false);
+	}
+
+	/// Pushes the result of an expansion, which is split into fields later unless it is quoted.
+	fn push_expansion(&mut self, s: &[u8]) {
+		if self.quoted {
+			self.push_quoted(s);
+		} else {
+			self.out.extend(s);
+			self.splittable.extend(s.iter().map(|_| true));
+		}
+	}
+
+	fn break_field(&mut self) {
+		let out = ::std::mem::replace(&mut self.out, vec![]);
+		let splittable = ::std::mem::replace(&mut self.splittable, vec![]);
+		self.fields.push((out, splittable, self.is_quoted_field));
+		self.is_quoted_field = false;
+	}
+
+	fn push_parameter(&mut self, name: &[u8], index: Option<&[u8]>) -> ExpandResult<()> {
+		match self.lookup(name, index) {
+			Some(Value::Scalar(value)) => self.push_expansion(&value),
+			Some(Value::List(values, true)) if self.quoted => {
+				let separator = ifs(self.state).first().cloned();
+				let mut joined = vec![];
+				for (n, value) in values.iter().enumerate() {
+					if n > 0 {
+						joined.extend(separator);
+					}
+					joined.extend(value);
+				}
+				self.push_expansion(&joined);
+			},
+			Some(Value::List(values, _)) => {
+				self.list_expanded = true;
+				for (n, value) in values.iter().enumerate() {
+					if n > 0 {
+						self.break_field();
+					}
+					self.push_expansion(value);
+				}
+			},
+			None => if self.state.options.nounset {
+				return Err(ExpandError::Unbound(name.to_vec()));
+			},
+		}
+		Ok(())
+	}
+
+	/// Expands `$(( expression ))` whose `$` has already been consumed.
+	fn expand_arithmetic(&mut self) -> ExpandResult<()> {
+		let mut depth = 0;
+		let mut close = None;
+		for (n, &c) in self.word[self.i ..].iter().enumerate() {
+			match c {
+				b'(' => { depth += 1; },
+				b')' => {
+					depth -= 1;
+					if depth == 0 {
+						close = Some(self.i + n);
+						break;
+					}
+				},
+				_ => {},
+			}
+		}
+		let close = match close {
+			Some(close) if self.word[close - 1] == b')' => close,
+			_ => { return Err(ExpandError::BadSubstitution(self.word[self.i - 1 ..].to_vec())); },
+		};
+		let expr = expand_word(self.state, &self.word[self.i + 2 .. close - 1])?;
+		self.i = close + 1;
+		let n = eval_arithmetic(self.state, &expr)?;
+		self.push_expansion(&n.to_string().into_bytes());
+		Ok(())
+	}
+
+	/// Expands `$( command )` whose `$` has already been consumed.
+	fn expand_command_substitution(&mut self) -> ExpandResult<()> {
+		let close = match find_close_paren(self.word, self.i) {
+			Some(close) => close,
+			None => { return Err(ExpandError::BadSubstitution(self.word[self.i - 1 ..].to_vec())); },
+		};
+		let word = self.word;
+		let output = eval::command_substitution(self.state, &word[self.i + 1 .. close]);
+		self.i = close + 1;
+		self.push_expansion(&output);
+		Ok(())
+	}
+
+	/// Expands `` `command` `` whose opening backquote has already been consumed. Inside, a backslash
+	/// only quotes `$`, `` ` ``, `\\` and, in double quotes, `"`.
+	fn expand_backquoted(&mut self) -> ExpandResult<()> {
+		let orig = self.i - 1;
+		let mut script = vec![];
+		loop {
+			match self.word.get(self.i) {
+				Some(&b'`') => { break; },
+				Some(&b'\\') => match self.word.get(self.i + 1) {
+					Some(&e) if e == b'$' || e == b'`' || e == b'\\' || (e == b'"' && self.quoted) => {
+						script.push(e);
+						self.i += 2;
+					},
+					_ => {
+						script.push(b'\\');
+						self.i += 1;
+					},
+				},
+				Some(&c) => {
+					script.push(c);
+					self.i += 1;
+				},
+				None => { return Err(ExpandError::BadSubstitution(self.word[orig ..].to_vec())); },
+			}
+		}
+		self.i += 1;
+		let output = eval::command_substitution(self.state, &script);
+		self.push_expansion(&output);
+		Ok(())
+	}
+
+	/// Replaces `<(...)` or `>(...)`, whose `<` or `>` has already been consumed, with the path of a pipe to the command.
+	fn expand_process_substitution(&mut self, is_input: bool) -> ExpandResult<()> {
+		let close = match find_close_paren(self.word, self.i) {
+			Some(close) => close,
+			None => { return Err(ExpandError::BadSubstitution(self.word[self.i - 1 ..].to_vec())); },
+		};
+		let word = self.word;
+		if let Some(fd) = eval::process_substitution(self.state, &word[self.i + 1 .. close], is_input) {
+			for &c in format!("/dev/fd/{}", fd).as_bytes() {
+				self.push_unquoted(c);
+			}
+		}
+		self.i = close + 1;
+		Ok(())
+	}
+
+	/// Expands a parameter whose `$` has already been consumed.
+	fn expand_parameter(&mut self) -> ExpandResult<()> {
+		let orig = self.i;
+		match self.word.get(self.i) {
+			Some(&b'(') if self.word.get(self.i + 1) == Some(&b'(') => self.expand_arithmetic()?,
+			Some(&b'(') => self.expand_command_substitution()?,
+			Some(&b'{') => {
+				let close = match self.word[self.i ..].iter().position(|&c| c == b'}') {
+					Some(n) => self.i + n,
+					None => { return Err(ExpandError::BadSubstitution(self.word[orig - 1 ..].to_vec())); },
+				};
+				let word = self.word;
+				let body = &word[self.i + 1 .. close];
+				self.i = close + 1;
+				let (name, index) = match body.iter().position(|&c| c == b'[') {
+					Some(n) if body.last() == Some(&b']') => (&body[.. n], Some(&body[n + 1 .. body.len() - 1])),
+					_ => (body, None),
+				};
+				let is_number = !name.is_empty() && name.iter().all(|&c| is_digit(c));
+				if !(is_name(name) || is_special(name) || is_number) {
+					return Err(ExpandError::BadSubstitution(self.word[orig - 1 .. self.i].to_vec()));
+				}
+				self.push_parameter(name, index)?;
+			},
+			Some(&c) if is_special(&[c]) => {
+				self.i += 1;
+				self.push_parameter(&[c], None)?;
+			},
+			Some(&c) if is_name_start(c) => {
+				while self.word.get(self.i).map_or(false, |&c| is_name_char(c)) {
+					self.i += 1;
+				}
+				let word = self.word;
+				self.push_parameter(&word[orig .. self.i], None)?;
+			},
+			_ => if self.quoted { self.push_quoted(b"$") } else { self.push_unquoted(b'$') },
+		}
+		Ok(())
+	}
+
+	fn expand_double_quoted(&mut self) -> ExpandResult<()> {
+		self.quoted = true;
+		self.list_expanded = false;
+		while let Some(&c) = self.word.get(self.i) {
+			self.i += 1;
+			match c {
+				b'"' => { break; },
+				b'\\' => match self.word.get(self.i) {
+					Some(&e) if e == b'$' || e == b'`' || e == b'"' || e == b'\\' => {
+						self.push_quoted(&[e]);
+						self.i += 1;
+					},
+					Some(&b'\n') => { self.i += 1; },
+					_ => self.push_quoted(b"\\"),
+				},
+				b'$' => self.expand_parameter()?,
+				b'`' => self.expand_backquoted()?,
+				_ => self.push_quoted(&[c]),
+			}
+		}
+		self.quoted = false;
+		if !self.list_expanded {
+			self.is_quoted_field = true;
+		}
+		Ok(())
+	}
+
+	/// Whether the unquoted `~` at `word[i]` starts a tilde prefix.
+	fn is_tilde_start(&self, i: usize) -> bool {
+		i == 0 || (self.is_assignment && self.word[i - 1] == b':' && (i < 2 || self.word[i - 2] != b'\\'))
+	}
+
+	/// Expands the tilde prefix after the `~` at `word[i - 1]`, which names the home directory of a user,
+	/// or of the current user when it is empty. A prefix with quoted characters or an unknown user is left alone.
+	fn expand_tilde(&mut self) {
+		let word = self.word;
+		let end = word[self.i ..].iter().position(|&c| c == b'/' || (self.is_assignment && c == b':')).map_or(word.len(), |n| self.i + n);
+		let user = &word[self.i .. end];
+		if user.iter().any(|c| b"\\'\"$`".contains(c)) {
+			self.push_unquoted(b'~');
+			return;
+		}
+		let home = if user.is_empty() {
+			match self.state.vars.get(b"HOME") {
+				Some(&var::Value::Scalar(ref home)) => Some(home.clone()),
+				_ => unsafe{ home_dir(libc::getpwuid(libc::getuid())) },
+			}
+		} else {
+			match CString::new(user) {
+				Ok(user) => unsafe{ home_dir(libc::getpwnam(user.as_ptr())) },
+				Err(_) => None,
+			}
+		};
+		match home {
+			Some(home) => {
+				self.push_quoted(&home);
+				self.i = end;
+			},
+			None => self.push_unquoted(b'~'),
+		}
+	}
+
+	/// Joins the fields with spaces, for the places where a word expands to a single string.
+	fn into_string(self) -> Vec<u8> {
+		let mut expanded = vec![];
+		for (field, _, _) in self.fields {
+			expanded.extend(field);
+			expanded.push(b' ');
+		}
+		expanded.extend(self.out);
+		expanded
+	}
+
+	fn expand(&mut self) -> ExpandResult<()> {
+		while let Some(&c) = self.word.get(self.i) {
+			self.i += 1;
+			match c {
+				b'~' if self.is_tilde_start(self.i - 1) => self.expand_tilde(),
+				b'<' | b'>' if self.word.get(self.i) == Some(&b'(') => self.expand_process_substitution(c == b'<')?,
+				// A backslash and newline join two lines and are removed.
+				b'\\' if self.word.get(self.i) == Some(&b'\n') => { self.i += 1; },
+				b'\\' => if let Some(&e) = self.word.get(self.i) {
+					self.push_quoted(&[e]);
+					self.i += 1;
+				},
+				b'\'' => {
+					let word = self.word;
+					let close = word[self.i ..].iter().position(|&c| c == b'\'').map_or(word.len(), |n| self.i + n);
+					self.push_quoted(&word[self.i .. close]);
+					self.i = close + 1;
+				},
+				b'"' => self.expand_double_quoted()?,
+				b'$' => self.expand_parameter()?,
+				b'`' => self.expand_backquoted()?,
+				_ => self.push_unquoted(c),
+			}
+		}
+		Ok(())
+	}
+}
+
+unsafe fn home_dir(passwd: *const libc::passwd) -> Option<Vec<u8>> {
+	if passwd.is_null() {
+		None
+	} else {
+		Some(CStr::from_ptr((*passwd).pw_dir).to_bytes().to_vec())
+	}
+}
+
+/// Evaluates an expanded arithmetic expression. Errors carry the expression without surrounding blanks.
+pub fn eval_arithmetic(state: &mut global::State, expr: &[u8]) -> ExpandResult<i64> {
+	arith::eval(state, expr).map_err(|e| {
+		let start = expr.iter().position(|&c| c != b' ' && c != b'\t').unwrap_or(expr.len());
+		let end = expr.iter().rposition(|&c| c != b' ' && c != b'\t').map_or(start, |n| n + 1);
+		ExpandError::Arithmetic(expr[start .. end].to_vec(), e)
+	})
+}
+
+/// Performs parameter, arithmetic and command expansion and quote removal on a word as written in the source.
+/// The result is a single string, as in assignments and redirections.
+pub fn expand_word(state: &mut global::State, word: &[u8]) -> ExpandResult<Vec<u8>> {
+	expand_pattern(state, word, b"")
+}
+
+/// Expands the value of an assignment like `expand_word`, but also expands tildes after each `:`, as in `PATH=~/bin:~/sbin`.
+pub fn expand_assignment(state: &mut global::State, value: &[u8]) -> ExpandResult<Vec<u8>> {
+	let mut expander = Expander::new(state, value, b"");
+	expander.is_assignment = true;
+	expander.expand()?;
+	Ok(expander.into_string())
+}
+
+/// Characters special in shell patterns.
+pub const GLOB_SPECIALS: &'static [u8] = b"*?[]\\";
+/// Characters special in extended regular expressions.
+pub const REGEX_SPECIALS: &'static [u8] = b"\\.[]()*+?{}|^$";
+
+/// Expands a word that is used as a pattern. Quoted characters listed in `specials` are escaped
+/// with a backslash so that they match literally.
+pub fn expand_pattern(state: &mut global::State, word: &[u8], specials: &'static [u8]) -> ExpandResult<Vec<u8>> {
+	let mut expander = Expander::new(state, word, specials);
+	expander.expand()?;
+	Ok(expander.into_string())
+}
+
+/// Expands a word of a command into fields. The results of unquoted expansions are split on `IFS`,
+/// and a word that expands to nothing unquoted is removed.
+pub fn expand_fields(state: &mut global::State, word: &[u8]) -> ExpandResult<Vec<Vec<u8>>> {
+	let mut expander = Expander::new(state, word, b"");
+	expander.expand()?;
+	expander.break_field();
+	let ifs = ifs(expander.state);
+	let mut fields = vec![];
+	for (field, splittable, is_quoted) in expander.fields {
+		let is_literal: Vec<bool> = splittable.iter().map(|&s| !s).collect();
+		let split = split_fields(&field, &is_literal, &ifs, None);
+		if split.is_empty() && is_quoted {
+			fields.push(vec![]);
+		} else {
+			fields.extend(split);
+		}
+	}
+	Ok(fields)
+}
+
+const IFS_KEY: &'static [u8] = b"IFS";
+const DEFAULT_IFS: &'static [u8] = b" \t\n";
+
+/// Returns the field separators, which default to space, tab and newline when `IFS` is unset.
+pub fn ifs(state: &global::State) -> Vec<u8> {
+	match state.vars.get(IFS_KEY) {
+		Some(&var::Value::Scalar(ref s)) => s.clone(),
+		Some(&var::Value::Array(ref a)) => a.get(0).cloned().unwrap_or(vec![]),
+		None => DEFAULT_IFS.to_vec(),
+	}
+}
+
+/// Splits `s` into fields at the characters of `ifs`, except where `is_literal` is set.
+/// IFS whitespace around a delimiter is absorbed, and leading and trailing IFS whitespace is dropped.
+/// When `max` fields are reached, the last one takes the rest of `s`.
+pub fn split_fields(s: &[u8], is_literal: &[bool], ifs: &[u8], max: Option<usize>) -> Vec<Vec<u8>> {
+	let is_delimiter = |i: usize| !is_literal[i] && ifs.contains(&s[i]);
+	let is_whitespace = |i: usize| is_delimiter(i) && (s[i] == b' ' || s[i] == b'\t' || s[i] == b'\n');
+	let mut fields = vec![];
+	let mut i = 0;
+	while i < s.len() && is_whitespace(i) {
+		i += 1;
+	}
+	while i < s.len() {
+		if max == Some(fields.len() + 1) {
+			let mut end = s.len();
+			while end > i && is_whitespace(end - 1) {
+				end -= 1;
+			}
+			fields.push(s[i .. end].to_vec());
+			break;
+		}
+		let start = i;
+		while i < s.len() && !is_delimiter(i) {
+			i += 1;
+		}
+		fields.push(s[start .. i].to_vec());
+		while i < s.len() && is_whitespace(i) {
+			i += 1;
+		}
+		if i < s.len() && is_delimiter(i) {
+			i += 1;
+			while i < s.len() && is_whitespace(i) {
+				i += 1;
+			}
+		}
+	}
+	fields
+}
+
+/// Returns the index just past the quoted part or substitution starting at `s[i]`, or `None` if there is none.
+fn skip_quoted(s: &[u8], i: usize) -> Option<usize> {
+	match s[i] {
+		b'\\' => Some(::std::cmp::min(i + 2, s.len())),
+		b'\'' => Some(s[i + 1 ..].iter().position(|&c| c == b'\'').map_or(s.len(), |n| i + 1 + n + 1)),
+		b'"' => {
+			let mut j = i + 1;
+			while j < s.len() && s[j] != b'"' {
+				if s[j] == b'\\' { j += 1; }
+				j += 1;
+			}
+			Some(::std::cmp::min(j + 1, s.len()))
+		},
+		b'$' if s.get(i + 1) == Some(&b'{') => Some(s[i ..].iter().position(|&c| c == b'}').map_or(s.len(), |n| i + n + 1)),
+		b'$' if s.get(i + 1) == Some(&b'(') => Some(find_close_paren(s, i + 1).map_or(s.len(), |n| n + 1)),
+		_ => None,
+	}
+}
+
+/// Finds the brace closing the one at `open`, and the top-level commas between them.
+fn find_close_brace(s: &[u8], open: usize) -> Option<(usize, Vec<usize>)> {
+	let mut depth = 0;
+	let mut commas = vec![];
+	let mut i = open;
+	while i < s.len() {
+		if let Some(next) = skip_quoted(s, i) {
+			i = next;
+			continue;
+		}
+		match s[i] {
+			b'{' => { depth += 1; },
+			b'}' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some((i, commas));
+				}
+			},
+			b',' if depth == 1 => commas.push(i),
+			_ => {},
+		}
+		i += 1;
+	}
+	None
+}
+
+fn parse_integer(s: &[u8]) -> Option<i64> {
+	let s = ::std::str::from_utf8(s).ok()?;
+	if s.is_empty() || s.starts_with('+') {
+		return None;
+	}
+	s.parse().ok()
+}
+
+/// Expands a sequence expression such as `1..10`, `01..10..2` or `a..e`.
+fn brace_sequence(body: &[u8]) -> Option<Vec<Vec<u8>>> {
+	let mut parts: Vec<&[u8]> = vec![];
+	let mut rest = body;
+	while let Some(n) = rest.windows(2).position(|w| w == b"..") {
+		parts.push(&rest[.. n]);
+		rest = &rest[n + 2 ..];
+	}
+	parts.push(rest);
+	if parts.len() < 2 || parts.len() > 3 {
+		return None;
+	}
+	// `None` for a step too large to take, which ends the sequence after its start.
+	let step = match parts.get(2) {
+		Some(step) => parse_integer(step)?.checked_abs(),
+		None => Some(1),
+	};
+	let step = if step == Some(0) { Some(1) } else { step };
+	let advance = |n: i64, is_up: bool| step.and_then(|step| if is_up { n.checked_add(step) } else { n.checked_sub(step) });
+	let (start, end) = (parts[0], parts[1]);
+	let mut items = vec![];
+	if let (Some(a), Some(b)) = (parse_integer(start), parse_integer(end)) {
+		let is_padded = |s: &[u8]| {
+			let digits = if s.starts_with(b"-") { &s[1 ..] } else { s };
+			digits.len() > 1 && digits[0] == b'0'
+		};
+		let width = if is_padded(start) || is_padded(end) { ::std::cmp::max(start.len(), end.len()) } else { 0 };
+		let mut n = a;
+		loop {
+			let item = if n < 0 {
+				format!("-{:0width$}", -(n as i128), width = width.saturating_sub(1))
+			} else {
+				format!("{:0width$}", n, width = width)
+			};
+			items.push(item.into_bytes());
+			if n == b {
+				break;
+			}
+			match advance(n, a < b) {
+				Some(next) if (a < b && next <= b) || (a > b && next >= b) => { n = next; },
+				_ => { break; },
+			}
+		}
+		return Some(items);
+	}
+	match (start, end) {
+		(&[a], &[b]) if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+			let mut c = a as i64;
+			loop {
+				items.push(vec![c as u8]);
+				if c == b as i64 {
+					break;
+				}
+				c = match advance(c, a < b) {
+					Some(c) => c,
+					None => { break; },
+				};
+				if (a < b && c > b as i64) || (a > b && c < b as i64) {
+					break;
+				}
+			}
+			Some(items)
+		},
+		_ => None,
+	}
+}
+
+/// Performs brace expansion on a word as written in the source. Quoted braces are not expanded.
+pub fn expand_braces(word: &[u8]) -> Vec<Vec<u8>> {
+	let mut i = 0;
+	while i < word.len() {
+		if let Some(next) = skip_quoted(word, i) {
+			i = next;
+			continue;
+		}
+		if word[i] == b'{' {
+			if let Some((close, commas)) = find_close_brace(word, i) {
+				let body = &word[i + 1 .. close];
+				let alternatives: Option<Vec<Vec<u8>>> = if !commas.is_empty() {
+					let mut bounds = vec![i];
+					bounds.extend(commas);
+					bounds.push(close);
+					Some(bounds.windows(2).map(|w| word[w[0] + 1 .. w[1]].to_vec()).collect())
+				} else {
+					brace_sequence(body)
+				};
+				if let Some(alternatives) = alternatives {
+					let mut words = vec![];
+					for alternative in alternatives {
+						let mut rest = alternative;
+						rest.extend(&word[close + 1 ..]);
+						for expanded in expand_braces(&rest) {
+							let mut w = word[.. i].to_vec();
+							w.extend(expanded);
+							words.push(w);
+						}
+					}
+					return words;
+				}
+			}
+		}
+		i += 1;
+	}
+	vec![word.to_vec()]
+}
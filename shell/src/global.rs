@@ -1,15 +1,45 @@
 use search;
 use job;
+use var;
+use options;
+use trap;
+use dir;
+use builtin;
+
+use std::os::unix::io::RawFd;
 
 pub struct State {
 	pub search_cache: search::SearchCache,
 	pub job_set: job::JobSet,
+	pub vars: var::Variables,
+	pub last_status: u8,
+	/// The status to exit with, set by `exit`, errexit or an expansion error to stop the commands being run.
+	pub exiting: Option<u8>,
+	pub options: options::Options,
+	pub traps: trap::Traps,
+	pub dirs: dir::Dirs,
+	/// `$0` followed by the positional parameters.
+	pub args: Vec<Vec<u8>>,
+	/// The status of the last command substitution in the command being expanded.
+	pub substitution_status: Option<u8>,
+	/// The shell's ends of the pipes of process substitutions, which are closed when the command is done.
+	pub substitution_fds: Vec<RawFd>,
+	pub builtins: builtin::Registry,
+	/// The modes of the terminal when the shell became interactive, restored whenever a foreground job gives it back.
+	pub terminal_modes: Option<job::TerminalModes>,
 }
 
 impl State {
 	pub fn new() -> State {
-		let search_cache = search::SearchCache::new();
 		let job_set = job::JobSet::new();
-		State { search_cache: search_cache, job_set: job_set }
+		let mut vars = var::Variables::new();
+		let search_cache = search::SearchCache::new(vars.get_str(search::PATH_KEY));
+		let dirs = dir::Dirs::new();
+		vars.set_scalar(b"PWD", &dirs.pwd);
+		vars.export(b"PWD");
+		State { search_cache: search_cache, job_set: job_set, vars: vars, last_status: 0, exiting: None,
+		        options: options::Options::new(), traps: trap::Traps::new(), dirs: dirs, args: vec![b"ish".to_vec()],
+		        substitution_status: None, substitution_fds: vec![], builtins: builtin::Registry::new(),
+		        terminal_modes: None }
 	}
 }
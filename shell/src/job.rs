@@ -1,8 +1,56 @@
+use std::fmt;
 use std::os::unix::io::RawFd;
 use libc::pid_t;
 use nix;
-use nix::unistd;
-use nix::sys::wait::WaitStatus;
+use nix::{fcntl,unistd};
+use nix::sys::{signal,stat,termios};
+use nix::sys::wait::{self,WaitStatus};
+use nix::sys::signal::Signal;
+use trap;
+
+/// The copy of the terminal is kept above the fds that scripts use and the ones that redirections save.
+const TERMINAL_FD_MIN: RawFd = 255;
+
+/// Finds the controlling terminal, and returns a copy of it that is closed on exec, or `None` if there is none
+/// or the shell is not in its foreground process group.
+/// `/dev/tty` is tried first so that the terminal is found even when the standard fds are redirected, as in `ish > log`.
+pub fn open_terminal() -> Option<RawFd> {
+	let (fd, is_opened) = match fcntl::open("/dev/tty", fcntl::O_RDWR | fcntl::O_CLOEXEC, stat::Mode::empty()) {
+		Ok(fd) => (fd, true),
+		Err(_) => match (0 .. 3).find(|&fd| unistd::isatty(fd).unwrap_or(false)) {
+			Some(fd) => (fd, false),
+			None => { return None; },
+		},
+	};
+	// A shell started in the background must not take the terminal from the job in the foreground.
+	let is_foreground = unistd::tcgetpgrp(fd).map_or(false, |pgrp| pgrp == unistd::getpgrp());
+	let copy = if is_foreground { fcntl::fcntl(fd, fcntl::FcntlArg::F_DUPFD_CLOEXEC(TERMINAL_FD_MIN)).ok() } else { None };
+	if is_opened {
+		let _ = unistd::close(fd);
+	}
+	copy
+}
+
+/// The modes of a terminal, saved to be restored later.
+#[derive(Clone, Copy)]
+pub struct TerminalModes(termios::Termios);
+
+impl TerminalModes {
+	pub fn get(fd: RawFd) -> nix::Result<TerminalModes> {
+		termios::tcgetattr(fd).map(TerminalModes)
+	}
+
+	/// Restores the modes once the output written so far is sent.
+	pub fn set(&self, fd: RawFd) -> nix::Result<()> {
+		termios::tcsetattr(fd, termios::SetArg::TCSADRAIN, &self.0)
+	}
+}
+
+impl fmt::Debug for TerminalModes {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "TerminalModes")
+	}
+}
 
 pub fn tcsetpgrp(fd: RawFd, pgrp: pid_t) -> nix::Result<()> {
 	unsafe {
@@ -50,68 +98,142 @@ impl WaitStatusExt for WaitStatus {
 	fn code(self) -> u8 {
 		match self {
 			WaitStatus::Exited(_, code) => code as u8,
-			WaitStatus::Signaled(_, sig, _) => sig as u8,
+			WaitStatus::Signaled(_, sig, _) => 128 + sig as u8,
+			WaitStatus::Stopped(_, sig) => 128 + sig as u8,
 			_ => 0,
 		}
 	}
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub fn signal_description(sig: Signal) -> &'static str {
+	match sig {
+		Signal::SIGHUP => "Hangup",
+		Signal::SIGINT => "Interrupt",
+		Signal::SIGQUIT => "Quit",
+		Signal::SIGILL => "Illegal instruction",
+		Signal::SIGTRAP => "Trace/breakpoint trap",
+		Signal::SIGABRT => "Aborted",
+		Signal::SIGBUS => "Bus error",
+		Signal::SIGFPE => "Floating point exception",
+		Signal::SIGKILL => "Killed",
+		Signal::SIGUSR1 => "User defined signal 1",
+		Signal::SIGSEGV => "Segmentation fault",
+		Signal::SIGUSR2 => "User defined signal 2",
+		Signal::SIGPIPE => "Broken pipe",
+		Signal::SIGALRM => "Alarm clock",
+		Signal::SIGTERM => "Terminated",
+		Signal::SIGSTOP => "Stopped (signal)",
+		Signal::SIGTSTP => "Stopped",
+		Signal::SIGTTIN => "Stopped (tty input)",
+		Signal::SIGTTOU => "Stopped (tty output)",
+		Signal::SIGXCPU => "CPU time limit exceeded",
+		Signal::SIGXFSZ => "File size limit exceeded",
+		Signal::SIGVTALRM => "Virtual timer expired",
+		Signal::SIGPROF => "Profiling timer expired",
+		Signal::SIGSYS => "Bad system call",
+		_ => "Unknown signal",
+	}
+}
+
+/// Describes a status for job listings: `Running`, `Stopped`, `Done`, `Exit N` or how a signal ended the process.
+pub fn describe_status(status: WaitStatus) -> String {
+	match status {
+		WaitStatus::Exited(_, 0) => "Done".to_string(),
+		WaitStatus::Exited(_, code) => format!("Exit {}", code),
+		WaitStatus::Signaled(_, sig, core_dumped) => format!("{}{}", signal_description(sig), if core_dumped { " (core dumped)" } else { "" }),
+		WaitStatus::Stopped(_, sig) => signal_description(sig).to_string(),
+		_ => "Running".to_string(),
+	}
+}
+
+/// Formats a line of `jobs`, such as `[1]+  Running                 sleep 10`.
+pub fn describe_job(id: usize, mark: char, status: WaitStatus, text: &[u8]) -> String {
+	format!("[{}]{}  {:<24}{}", id, mark, describe_status(status), String::from_utf8_lossy(text))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Proccess {
 	pub pid: pid_t,
 	pub status: WaitStatus,
+	/// The source of the command that the process runs.
+	pub text: Vec<u8>,
 }
 
 #[derive(Debug)]
 pub struct Job {
 	pub gid: pid_t,
+	/// The shell process that forked the job. Subshells that inherit the job cannot wait for it.
+	pub parent: pid_t,
 	pub proccesses: Vec<Proccess>,
+	/// The source of the pipeline or list that the job runs.
+	pub text: Vec<u8>,
+	pub is_background: bool,
+	/// The modes of the terminal when the job was stopped, which are restored when it is continued in the foreground.
+	pub modes: Option<TerminalModes>,
+	/// Set for the job of a process substitution, which is neither listed nor reported.
+	pub is_internal: bool,
 }
 
 impl Job {
 	pub fn state(&self) -> State {
 		self.proccesses.iter().map(|pr| pr.status.state()).min().unwrap()
 	}
+
+	/// The status of the rightmost process in the state of the job, which tells how the job got there.
+	pub fn status(&self) -> WaitStatus {
+		let state = self.state();
+		self.proccesses.iter().rev().find(|pr| pr.status.state() == state).unwrap().status
+	}
 }
 
 #[derive(Debug)]
 pub struct JobBuilder {
 	imp: Job,
+	terminal: Option<RawFd>,
 }
 
 impl JobBuilder {
-	pub fn new(size_hint: usize) -> JobBuilder {
+	/// Without a `terminal` there is no job control: the processes stay in the shell's process group.
+	pub fn new(text: &[u8], size_hint: usize, terminal: Option<RawFd>) -> JobBuilder {
 		JobBuilder {
-			imp: Job { gid: 0, proccesses: Vec::with_capacity(size_hint) }
+			imp: Job { gid: 0, parent: unistd::getpid(), proccesses: Vec::with_capacity(size_hint), text: text.to_vec(), is_background: false, modes: None, is_internal: false },
+			terminal: terminal,
 		}
 	}
 
-	pub fn push_fork(&mut self, is_background: bool) -> nix::Result<unistd::ForkResult> {
+	/// Forks a process of the job that runs the command `text`.
+	pub fn push_fork(&mut self, text: &[u8], is_background: bool) -> nix::Result<unistd::ForkResult> {
 		let job = &mut self.imp;
 
+		job.is_background = is_background;
 		let r = unistd::fork()?;
 		match r {
 			unistd::ForkResult::Parent{ child: pid } => {
 				if job.gid == 0 {
-					let _ = unistd::setpgid(pid, pid);
-					if !is_background {
-						let _ = unistd::tcsetpgrp(1, pid);
+					if let Some(terminal) = self.terminal {
+						let _ = unistd::setpgid(pid, pid);
+						if !is_background {
+							let _ = tcsetpgrp(terminal, pid);
+						}
 					}
 					job.gid = pid;
-				} else {
+				} else if self.terminal.is_some() {
 					let _ = unistd::setpgid(pid, job.gid);
 				}
-				job.proccesses.push(Proccess { pid: pid, status: WaitStatus::StillAlive });
+				job.proccesses.push(Proccess { pid: pid, status: WaitStatus::StillAlive, text: text.to_vec() });
 			},
 			unistd::ForkResult::Child => {
-				if job.gid == 0 {
-					let pid = unistd::getpid();
-					let _ = unistd::setpgid(pid, pid);
-					if !is_background {
-						let _ = tcsetpgrp(1, pid);
+				trap::reset_dispositions();
+				if let Some(terminal) = self.terminal {
+					if job.gid == 0 {
+						let pid = unistd::getpid();
+						let _ = unistd::setpgid(pid, pid);
+						if !is_background {
+							let _ = tcsetpgrp(terminal, pid);
+						}
+					} else {
+						let _ = unistd::setpgid(0, job.gid);
 					}
-				} else {
-					let _ = unistd::setpgid(0, job.gid);
 				}
 			},
 		}
@@ -127,23 +249,68 @@ impl JobBuilder {
 		self.imp.proccesses.reverse();
 		self.imp
 	}
+
+	/// Builds a job that is not shown to the user.
+	pub fn build_internal(self) -> Job {
+		let mut job = self.build();
+		job.is_internal = true;
+		job
+	}
 }
 
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-struct JobEvent {
-	job_idx: usize,
-	status: WaitStatus,
+/// A change of the state of a job, kept until it is reported.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JobEvent {
+	pub job_id: usize,
+	/// `+` for the current job, `-` for the previous one, and otherwise a space.
+	pub mark: char,
+	pub text: Vec<u8>,
+	pub status: WaitStatus,
+}
+
+impl JobEvent {
+	pub fn describe(&self) -> String {
+		describe_job(self.job_id, self.mark, self.status, &self.text)
+	}
 }
 
 #[derive(Debug)]
 pub struct JobSet {
 	jobs: Vec<Option<Job>>,
 	events: Vec<JobEvent>,
+	/// Indices of the jobs from the least to the most recently started or stopped.
+	recent: Vec<usize>,
+	/// The copy of the controlling terminal that foreground jobs are given, or `None` without job control.
+	terminal: Option<RawFd>,
 }
 
 impl JobSet {
-	fn update_job_set(&mut self, status: WaitStatus) -> usize {
+	fn touch(&mut self, job_idx: usize) {
+		if self.jobs[job_idx].as_ref().unwrap().is_internal {
+			return;
+		}
+		self.recent.retain(|&i| i != job_idx);
+		self.recent.push(job_idx);
+	}
+
+	fn mark(&self, job_idx: usize) -> char {
+		match self.recent.iter().rev().position(|&i| i == job_idx) {
+			Some(0) => '+',
+			Some(1) => '-',
+			_ => ' ',
+		}
+	}
+
+	fn remove(&mut self, job_idx: usize) {
+		self.jobs[job_idx] = None;
+		self.recent.retain(|&i| i != job_idx);
+		let len = self.jobs.iter().enumerate().rev().find(|&(_, job)| job.is_some()).map_or(0, |(i, _)| i + 1);
+		self.jobs.truncate(len);
+	}
+
+	/// Records a status of a process. Returns the index of its job, or `None` if it is not in a job.
+	fn update_job_set(&mut self, status: WaitStatus) -> Option<usize> {
 		let pid = status.get_pid().expect("wait returned 0");
 		let job_idx = {
 			let mut go = || {
@@ -155,19 +322,103 @@ impl JobSet {
 						} else {
 							continue;
 						}
-						let new_state = job.state();
-						if old_state != new_state {
-							let rightmost_pr = job.proccesses.iter().rev().find(|pr| pr.status.state() == new_state).unwrap();
-							self.events.push(JobEvent { job_idx: i, status: rightmost_pr.status });
-						}
-						return i;
+						return Some((i, old_state != job.state()));
 					}
 				}
-				panic!("unknown pid {}", pid)
+				None
 			};
 			go()
 		};
-		job_idx
+		let (job_idx, is_changed) = match job_idx {
+			Some(r) => r,
+			None => { return None; },
+		};
+		if is_changed && !self.jobs[job_idx].as_ref().unwrap().is_internal {
+			let (state, status, text) = {
+				let job = self.jobs[job_idx].as_ref().unwrap();
+				(job.state(), job.status(), job.text.clone())
+			};
+			if state == State::Stopped {
+				self.touch(job_idx);
+			}
+			let mark = self.mark(job_idx);
+			self.events.push(JobEvent { job_id: job_idx + 1, mark: mark, text: text, status: status });
+		}
+		Some(job_idx)
+	}
+
+	/// Waits for a change of state of a process of a job, retrying when interrupted.
+	/// Only the job's process group is waited for, so that children of the shell outside of jobs are left alone.
+	/// Without job control the processes have no group of their own, and they are waited for one by one.
+	/// Returns `false` if there was no change with `WNOHANG` or the job has no children left.
+	fn wait_job(&mut self, job_idx: usize, flags: wait::WaitPidFlag) -> bool {
+		let (pids, parent) = {
+			let job = self.jobs[job_idx].as_ref().unwrap();
+			let pids: Vec<pid_t> = if self.terminal.is_some() {
+				vec![-job.gid]
+			} else {
+				job.proccesses.iter().filter(|pr| pr.status.state() != State::Terminated).map(|pr| pr.pid).collect()
+			};
+			(pids, job.parent)
+		};
+		if parent != unistd::getpid() {
+			return false;
+		}
+		for pid in pids {
+			loop {
+				match wait::waitpid(pid, Some(flags | wait::WUNTRACED | wait::WCONTINUED)) {
+					Ok(WaitStatus::StillAlive) => { break; },
+					Ok(status) => {
+						self.update_job_set(status);
+						return true;
+					},
+					Err(nix::Error::Sys(nix::Errno::EINTR)) => {},
+					Err(_) => {
+						self.lose_job(job_idx);
+						return false;
+					},
+				}
+			}
+		}
+		false
+	}
+
+	/// Marks the processes of a job that were collected elsewhere, such as with SIGCHLD ignored, as done.
+	/// Their statuses are lost, so they count as exited with 127 like `wait` for an unknown process.
+	fn lose_job(&mut self, job_idx: usize) {
+		let lost: Vec<pid_t> = self.jobs[job_idx].as_ref().unwrap().proccesses.iter()
+			.filter(|pr| pr.status.state() != State::Terminated)
+			.map(|pr| pr.pid).collect();
+		for pid in lost {
+			self.update_job_set(WaitStatus::Exited(pid, 127));
+		}
+	}
+
+	/// Finds a job by `%N` or `N`, `%+`, `%%` or no spec for the current job, or `%-` for the previous one.
+	pub fn find(&mut self, spec: Option<&[u8]>) -> Option<JobDescriptor> {
+		let job_idx = match spec.map(|s| if s.starts_with(b"%") { &s[1 ..] } else { s }) {
+			None | Some(b"+") | Some(b"%") | Some(b"") => self.recent.last().cloned(),
+			Some(b"-") => self.recent.iter().rev().nth(1).cloned(),
+			Some(n) => ::std::str::from_utf8(n).ok().and_then(|n| n.parse::<usize>().ok()).and_then(|n| n.checked_sub(1)),
+		};
+		match job_idx {
+			Some(i) if self.jobs.get(i).map_or(false, |job| job.as_ref().map_or(false, |job| !job.is_internal)) =>
+				Some(JobDescriptor { job_idx: i, job_set: self }),
+			_ => None,
+		}
+	}
+
+	/// The jobs with their numbers and marks.
+	pub fn jobs(&self) -> Vec<(usize, char, &Job)> {
+		self.jobs.iter().enumerate()
+			.filter_map(|(i, job)| job.as_ref().map(|job| (i + 1, self.mark(i), job)))
+			.filter(|&(_, _, job)| !job.is_internal)
+			.collect()
+	}
+
+	/// Returns the changes of the states of jobs since the last call, such as background jobs that are done.
+	pub fn take_events(&mut self) -> Vec<JobEvent> {
+		self.events.drain(..).collect()
 	}
 
 	pub fn push(&mut self, job: Job) -> JobDescriptor {
@@ -184,11 +435,47 @@ impl JobSet {
 			};
 			go()
 		};
+		// The current job is the one most recently started in the background or stopped.
+		if self.jobs[job_idx].as_ref().unwrap().is_background {
+			self.touch(job_idx);
+		}
 		JobDescriptor { job_idx: job_idx, job_set: self }
 	}
 
+	/// A job set without job control, which `set_terminal` turns on.
 	pub fn new() -> JobSet {
-		JobSet { jobs: vec![], events: vec![] }
+		JobSet { jobs: vec![], events: vec![], recent: vec![], terminal: None }
+	}
+
+	pub fn terminal(&self) -> Option<RawFd> {
+		self.terminal
+	}
+
+	/// Turns job control on with a terminal from `open_terminal`, or off with `None`, closing the previous terminal.
+	pub fn set_terminal(&mut self, terminal: Option<RawFd>) {
+		if let Some(fd) = self.terminal {
+			if terminal != Some(fd) {
+				let _ = unistd::close(fd);
+			}
+		}
+		self.terminal = terminal;
+	}
+
+	/// Collects the children that have changed state without blocking, and forgets the jobs that are done.
+	pub fn reap(&mut self) {
+		for i in 0 .. self.jobs.len() {
+			while self.jobs[i].as_ref().map_or(false, |job| job.state() != State::Terminated) {
+				if !self.wait_job(i, wait::WNOHANG) {
+					break;
+				}
+			}
+		}
+		let done: Vec<usize> = self.jobs.iter().enumerate()
+			.filter(|&(_, job)| job.as_ref().map_or(false, |job| job.state() == State::Terminated))
+			.map(|(i, _)| i).collect();
+		for i in done {
+			self.remove(i);
+		}
 	}
 }
 
@@ -201,31 +488,81 @@ pub struct JobDescriptor<'a> {
 impl<'a> Drop for JobDescriptor<'a> {
 	fn drop(&mut self) {
 		if self.job().state() == State::Terminated {
-			let jobs = &mut self.job_set.jobs;
-			jobs[self.job_idx] = None;
-			let len = jobs.iter().enumerate().rev().find(|&(_, pr)| pr.is_some()).map_or(0, |(i, _)| i + 1);
-			jobs.truncate(len);
+			self.job_set.remove(self.job_idx);
 		}
 	}
 }
 
 impl<'a> JobDescriptor<'a> {
+	/// The number of the job, as shown by `jobs`.
+	pub fn id(&self) -> usize {
+		self.job_idx + 1
+	}
+
 	pub fn job(&self) -> &Job {
 		self.job_set.jobs[self.job_idx].as_ref().unwrap()
 	}
 
+	fn job_mut(&mut self) -> &mut Job {
+		self.job_set.jobs[self.job_idx].as_mut().unwrap()
+	}
+
+	/// Waits for the job in the foreground, and then takes the terminal back and restores `shell_modes` on it.
+	/// If the job has stopped, its modes are saved to be restored when it is continued.
+	pub fn wait_foreground(&mut self, shell_modes: Option<TerminalModes>) {
+		self.wait();
+		let terminal = match self.job_set.terminal {
+			Some(terminal) => terminal,
+			None => { return; },
+		};
+		let _ = tcsetpgrp(terminal, unistd::getpgrp());
+		if self.job().state() == State::Stopped {
+			self.job_mut().modes = TerminalModes::get(terminal).ok();
+		}
+		if let Some(modes) = shell_modes {
+			let _ = modes.set(terminal);
+		}
+	}
+
+	/// Continues a stopped job. In the foreground, the job is given the terminal with the modes saved when it stopped.
+	/// This fails with `ENOTTY` without job control, where the job has no process group of its own to signal.
+	pub fn resume(&mut self, is_foreground: bool) -> nix::Result<()> {
+		let terminal = match self.job_set.terminal {
+			Some(terminal) => terminal,
+			None => { return Err(nix::Error::Sys(nix::Errno::ENOTTY)); },
+		};
+		let gid = self.job().gid;
+		if is_foreground {
+			let _ = tcsetpgrp(terminal, gid);
+			if let Some(modes) = self.job().modes {
+				let _ = modes.set(terminal);
+			}
+		}
+		signal::kill(-gid, Signal::SIGCONT)?;
+		for pr in self.job_mut().proccesses.iter_mut() {
+			if pr.status.state() == State::Stopped {
+				pr.status = WaitStatus::Continued(pr.pid);
+			}
+		}
+		Ok(())
+	}
+
+	/// Waits until the job changes state: until it is done or stopped, or continued if it is stopped.
 	pub fn wait(&mut self) {
 		let wait_state = self.job().state();
 		loop {
-			let status = nix::sys::wait::wait().expect("wait errored");
-			let job_idx = self.job_set.update_job_set(status);
-			let job = self.job_set.jobs[job_idx].as_ref().unwrap();
-			if job_idx == self.job_idx && job.state() != wait_state {
-				if let (State::Terminated, WaitStatus::Exited(..)) = (job.state(), job.proccesses.last().unwrap().status) {
+			let is_collected = self.job_set.wait_job(self.job_idx, wait::WaitPidFlag::empty());
+			let state = self.job().state();
+			if state != wait_state {
+				// The status of a foreground job that is done is reported right away, not as an event.
+				if state == State::Terminated {
 					self.job_set.events.pop();
 				}
 				return;
 			}
+			if !is_collected {
+				return;
+			}
 		}
 	}
 }
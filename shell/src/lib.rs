@@ -0,0 +1,166 @@
+//! ish, a Unix shell, as a library that tools can embed as a scripting layer.
+//! The `ish` binary is a front-end to the `Shell` type defined here.
+
+extern crate libc;
+extern crate nix;
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+
+pub mod parser;
+pub mod ast;
+pub mod error;
+mod search;
+mod job;
+mod global;
+mod builtin;
+mod eval;
+mod var;
+mod expand;
+mod options;
+mod trap;
+mod arith;
+mod cond;
+mod printf;
+mod dir;
+
+pub use var::Value;
+pub use eval::Outcome;
+pub use builtin::{Builtin,CompletionHint,Io,Stream};
+
+use std::rc::Rc;
+
+/// A shell with its variables, positional parameters, traps and jobs.
+// `repr(transparent)` is what makes `from_state` sound, and must not be removed.
+#[repr(transparent)]
+pub struct Shell {
+	state: global::State,
+}
+
+impl Shell {
+	/// Creates a shell that takes its exported variables and working directory from the process.
+	pub fn new() -> Shell {
+		Shell { state: global::State::new() }
+	}
+
+	/// Views the state of a running shell as the `Shell` that owns it, for custom builtins.
+	/// Only `builtin::run` calls it; it is private to the crate root and is not part of the API.
+	fn from_state(state: &mut global::State) -> &mut Shell {
+		// SAFETY: `Shell` is `repr(transparent)` over its only field, a `State`, so the two types have the same
+		// size, alignment and layout, and a valid `State` is a valid `Shell`. The returned reference reborrows
+		// `state` with the same lifetime, so the borrow rules still prevent any aliasing.
+		unsafe{ &mut *(state as *mut global::State as *mut Shell) }
+	}
+
+	/// Parses a whole script without running it.
+	pub fn parse<'a>(source: &'a [u8]) -> Result<parser::List<'a>, parser::ParseError> {
+		parser::parse(source)
+	}
+
+	/// Runs commands that were parsed with `parse`, and returns the status of the last one,
+	/// or `Outcome::Exit` when they asked the shell to exit. The process is left running either way.
+	pub fn eval(&mut self, list: &parser::List) -> Outcome {
+		let s = eval::eval(&mut self.state, list);
+		eval::take_outcome(&mut self.state, s)
+	}
+
+	/// Runs commands of an owned syntax tree, such as one kept after its source is gone.
+	pub fn eval_ast(&mut self, list: &ast::List) -> Outcome {
+		self.eval(&parser::List::from(list))
+	}
+
+	/// Parses and runs a script. Nothing is run when it does not parse.
+	pub fn eval_str(&mut self, source: &[u8]) -> Result<Outcome, parser::ParseError> {
+		let list = parser::parse(source)?;
+		Ok(self.eval(&list))
+	}
+
+	/// Runs a script read from `path` command by command like a shell script,
+	/// and reports parse errors on stderr with the file name and line number.
+	pub fn eval_file(&mut self, source: &[u8], path: &[u8]) -> Outcome {
+		let s = eval::eval_file(&mut self.state, source, path);
+		eval::take_outcome(&mut self.state, s)
+	}
+
+	pub fn var(&self, name: &[u8]) -> Option<&Value> {
+		self.state.vars.get(name)
+	}
+
+	pub fn set_var(&mut self, name: &[u8], value: Value) {
+		self.state.vars.set(name, value);
+	}
+
+	/// Marks a variable to be passed to the environment of commands.
+	pub fn export_var(&mut self, name: &[u8]) {
+		self.state.vars.export(name);
+	}
+
+	pub fn unset_var(&mut self, name: &[u8]) {
+		self.state.vars.unset(name);
+	}
+
+	/// Sets `$0` and the positional parameters, `$0` being the first element.
+	pub fn set_args(&mut self, args: Vec<Vec<u8>>) {
+		assert!(!args.is_empty(), "$0 is required");
+		self.state.args = args;
+	}
+
+	/// The value of `$?`.
+	pub fn last_status(&self) -> u8 {
+		self.state.last_status
+	}
+
+	pub fn set_last_status(&mut self, s: u8) {
+		self.state.last_status = s;
+	}
+
+	/// Makes expansion errors not exit the shell, as when it reads commands from a terminal.
+	/// Job control is turned on if the shell is in the foreground of its controlling terminal,
+	/// and the modes of the terminal are saved to be restored after each foreground job.
+	pub fn set_interactive(&mut self, interactive: bool) {
+		self.state.options.interactive = interactive;
+		self.state.job_set.set_terminal(if interactive { job::open_terminal() } else { None });
+		self.state.terminal_modes = self.state.job_set.terminal().and_then(|fd| job::TerminalModes::get(fd).ok());
+	}
+
+	/// Adds a command that replaces the builtin of the same name and takes precedence over commands in `PATH`.
+	/// A function or closure taking `(&mut Shell, &mut Io, &[&[u8]])` is a builtin without help text.
+	pub fn register_builtin<B: Builtin + 'static>(&mut self, name: &[u8], builtin: B) {
+		self.state.builtins.insert(name, Rc::new(builtin));
+	}
+
+	/// Removes a builtin, including one of the shell's own.
+	pub fn unregister_builtin(&mut self, name: &[u8]) {
+		self.state.builtins.remove(name);
+	}
+
+	/// Looks up a builtin, for example to show its help text or to complete its arguments.
+	pub fn builtin(&self, name: &[u8]) -> Option<Rc<Builtin>> {
+		self.state.builtins.get(name)
+	}
+
+	/// The names of all builtins in byte order.
+	pub fn builtin_names(&self) -> Vec<&[u8]> {
+		self.state.builtins.names()
+	}
+
+	/// Reports on stderr the jobs that have stopped or are done since the last call.
+	pub fn notify_jobs(&mut self) {
+		eval::notify_jobs(&mut self.state);
+	}
+
+	/// Runs the traps of the signals received since the last call, which can ask the shell to exit.
+	pub fn run_pending_traps(&mut self) -> Outcome {
+		eval::run_pending_traps(&mut self.state);
+		let s = self.state.last_status;
+		eval::take_outcome(&mut self.state, s)
+	}
+
+	/// Runs the EXIT trap, as the shell does before it exits, and returns the status to exit with.
+	/// Exiting the process is left to the caller.
+	pub fn run_exit_trap(&mut self, s: u8) -> u8 {
+		eval::run_exit_trap(&mut self.state, s)
+	}
+}
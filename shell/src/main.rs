@@ -1,44 +1,172 @@
-mod parser;
-mod search;
-mod job;
-mod global;
-mod builtin;
-mod eval;
+extern crate ish;
+#[cfg(feature = "serialize")]
+extern crate serde_json;
+
 #[cfg(test)]
 mod test;
 
-extern crate libc;
-extern crate nix;
-
-use std::io;
+use std::{env,fs,io,process};
+use std::ffi::{OsStr,OsString};
+use std::os::unix::ffi::{OsStrExt,OsStringExt};
 use io::prelude::*;
+use ish::{error,parser};
 
 const PROMPT: &'static [u8] = b"ish> ";
+const PS2_KEY: &'static [u8] = b"PS2";
+const DEFAULT_PS2: &'static [u8] = b"> ";
+
+/// Reads a script file, and reports the error with the status to exit with if it cannot be read.
+fn read_script(path: &OsStr) -> Result<Vec<u8>, u8> {
+	let mut script: Vec<u8> = vec![];
+	match fs::File::open(path).and_then(|mut f| f.read_to_end(&mut script)) {
+		Ok(_) => Ok(script),
+		Err(e) => {
+			error::report(path.to_string_lossy(), error::describe_io(&e));
+			Err(127)
+		},
+	}
+}
+
+fn run_script(shell: &mut ish::Shell, path: &OsStr) -> u8 {
+	match read_script(path) {
+		Ok(script) => shell.eval_file(&script, path.as_bytes()).status(),
+		Err(s) => s,
+	}
+}
+
+/// Exits after running the EXIT trap of the shell.
+fn exit(shell: &mut ish::Shell, s: u8) -> ! {
+	let s = shell.run_exit_trap(s);
+	process::exit(s as i32)
+}
+
+/// Reports a parse error of interactive input with the line and a marker under the fault, and returns its status.
+fn report_parse_error(line: &[u8], e: parser::ParseError) -> u8 {
+	let caret = e.caret(line);
+	let s = error::report_error(&error::Error::Parse(e));
+	let _ = writeln!(&mut io::stderr(), "{}", caret);
+	s
+}
+
+/// Prints the syntax tree of a script as JSON on one line, for tools that inspect scripts without running them.
+#[cfg(feature = "serialize")]
+fn dump_ast(source: &[u8]) -> u8 {
+	match ish::ast::parse(source) {
+		Ok(list) => {
+			println!("{}", serde_json::to_string(&list).expect("the syntax tree is serialisable"));
+			0
+		},
+		Err(e) => report_parse_error(source, e),
+	}
+}
+
+#[cfg(not(feature = "serialize"))]
+fn dump_ast(_: &[u8]) -> u8 {
+	error::report("--dump-ast", "ish was built without the serialize feature");
+	2
+}
 
 fn main() {
+	let mut args: Vec<OsString> = env::args_os().skip(1).collect();
+	let is_dump_ast = args.first().map_or(false, |a| a == "--dump-ast");
+	if is_dump_ast {
+		args.remove(0);
+	}
+	// `-c command [name [arguments]]` runs `command` with `name` as `$0`.
+	let command = if args.first().map_or(false, |a| a == "-c") {
+		args.remove(0);
+		if args.is_empty() {
+			error::report("-c", "option requires an argument");
+			process::exit(2);
+		}
+		Some(args.remove(0).into_vec())
+	} else {
+		None
+	};
+
+	if is_dump_ast {
+		let source = match command {
+			Some(command) => command,
+			None => match args.first() {
+				Some(path) => read_script(path).unwrap_or_else(|s| process::exit(s as i32)),
+				None => {
+					error::report("--dump-ast", "-c command or a script file required");
+					process::exit(2);
+				},
+			},
+		};
+		process::exit(dump_ast(&source) as i32);
+	}
+
+	let mut shell = ish::Shell::new();
+	if let Some(command) = command {
+		if !args.is_empty() {
+			shell.set_args(args.into_iter().map(|a| a.into_vec()).collect());
+		}
+		let s = match shell.eval_str(&command) {
+			Ok(outcome) => outcome.status(),
+			Err(e) => report_parse_error(&command, e),
+		};
+		exit(&mut shell, s);
+	}
+	if let Some(path) = args.first().cloned() {
+		shell.set_args(args.into_iter().map(|a| a.into_vec()).collect());
+		let s = run_script(&mut shell, &path);
+		exit(&mut shell, s);
+	}
+
+	shell.set_interactive(true);
 	let mut stdout = io::stdout();
 	let stdin = io::stdin();
 	let mut stdin_locked = stdin.lock();
-	let mut state = global::State::new();
+	let mut line: Vec<u8> = vec![];
 	loop {
-		let _ = stdout.write(PROMPT);
+		if line.is_empty() {
+			shell.notify_jobs();
+			if let ish::Outcome::Exit(s) = shell.run_pending_traps() {
+				exit(&mut shell, s);
+			}
+			let _ = stdout.write(PROMPT);
+		} else {
+			// Continue a command that is not complete yet.
+			let ps2 = match shell.var(PS2_KEY) {
+				Some(&ish::Value::Scalar(ref ps2)) => ps2.clone(),
+				_ => DEFAULT_PS2.to_vec(),
+			};
+			let _ = stdout.write(&ps2);
+		}
 		let _ = stdout.flush();
-		let mut line: Vec<u8> = vec![];
 		match stdin_locked.read_until(b'\n', &mut line) {
-			Ok(0) => { return; },
+			Ok(0) if line.is_empty() => {
+				let s = shell.last_status();
+				exit(&mut shell, s);
+			},
+			Ok(0) => {
+				if let Err(e) = ish::Shell::parse(&line) {
+					let s = report_parse_error(&line, e);
+					shell.set_last_status(s);
+				}
+				line.clear();
+			},
 			Err(e) => {
-				use std::error::Error;
-				println!("read error: {:?}", e.description());
+				error::report("read error", error::describe_io(&e));
+				line.clear();
 			},
 			Ok(_) => {
-				let pipeline = match parser::parse(&line) {
-					Ok(p) => p,
+				let list = match ish::Shell::parse(&line) {
+					Ok(l) => l,
+					Err(ref e) if e.is_incomplete() => { continue; },
 					Err(e) => {
-						println!("parse error: {:?}", e);
+						let s = report_parse_error(&line, e);
+						shell.set_last_status(s);
+						line.clear();
 						continue;
 					},
 				};
-				eval::eval(&mut state, &pipeline);
+				if let ish::Outcome::Exit(s) = shell.eval(&list) {
+					exit(&mut shell, s);
+				}
+				line.clear();
 			}
 		}
 	}
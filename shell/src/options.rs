@@ -0,0 +1,68 @@
+#[derive(Debug, Default)]
+pub struct Options {
+	pub errexit: bool,
+	pub nounset: bool,
+	pub pipefail: bool,
+	pub xtrace: bool,
+	pub interactive: bool,
+}
+
+/// Options settable with `set -o`, along with their single-letter forms.
+pub const OPTION_NAMES: &'static [(&'static str, Option<u8>)] = &[
+	("errexit", Some(b'e')),
+	("nounset", Some(b'u')),
+	("pipefail", None),
+	("xtrace", Some(b'x')),
+];
+
+impl Options {
+	pub fn new() -> Options {
+		Options::default()
+	}
+
+	fn flag_mut(&mut self, name: &[u8]) -> Option<&mut bool> {
+		match name {
+			b"errexit" => Some(&mut self.errexit),
+			b"nounset" => Some(&mut self.nounset),
+			b"pipefail" => Some(&mut self.pipefail),
+			b"xtrace" => Some(&mut self.xtrace),
+			_ => None,
+		}
+	}
+
+	pub fn get(&self, name: &str) -> bool {
+		match name {
+			"errexit" => self.errexit,
+			"nounset" => self.nounset,
+			"pipefail" => self.pipefail,
+			"xtrace" => self.xtrace,
+			_ => false,
+		}
+	}
+
+	/// Returns false if there is no such option.
+	pub fn set(&mut self, name: &[u8], enable: bool) -> bool {
+		match self.flag_mut(name) {
+			Some(flag) => { *flag = enable; true },
+			None => false,
+		}
+	}
+
+	pub fn set_letter(&mut self, letter: u8, enable: bool) -> bool {
+		match OPTION_NAMES.iter().find(|&&(_, l)| l == Some(letter)) {
+			Some(&(name, _)) => self.set(name.as_bytes(), enable),
+			None => false,
+		}
+	}
+
+	/// The value of `$-`.
+	pub fn letters(&self) -> Vec<u8> {
+		let mut letters: Vec<u8> = OPTION_NAMES.iter()
+			.filter_map(|&(name, l)| if self.get(name) { l } else { None })
+			.collect();
+		if self.interactive {
+			letters.push(b'i');
+		}
+		letters
+	}
+}
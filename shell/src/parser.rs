@@ -1,7 +1,9 @@
 use std;
-use std::error::Error;
+use std::{error,fmt};
+use cond;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub enum RedirectType { Input, Output, Append }
 
 #[derive(Debug)]
@@ -11,20 +13,169 @@ pub struct Redirect<'a> {
 	pub typ: RedirectType,
 }
 
+/// A simple command. `name` is empty when the command consists only of assignments and redirects.
 #[derive(Debug)]
-pub struct Command<'a> {
+pub struct SimpleCommand<'a> {
+	pub assignments: Vec<&'a [u8]>,
 	pub name: &'a [u8],
 	pub arguments: Vec<&'a [u8]>,
 	pub redirects: Vec<Redirect<'a>>,
 }
 
+#[derive(Debug)]
+pub enum CompoundCommand<'a> {
+	BraceGroup(List<'a>),
+	If { conditions: Vec<(List<'a>, List<'a>)>, otherwise: Option<List<'a>> },
+	While { is_until: bool, condition: List<'a>, body: List<'a> },
+	/// `(( expression ))`, holding the expression as written.
+	Arithmetic(&'a [u8]),
+	/// `[[ expression ]]`.
+	Conditional(CondExpr<'a>),
+}
+
+/// An expression of a `[[ ]]` command. Operands are words as written in the source.
+#[derive(Debug)]
+pub enum CondExpr<'a> {
+	Word(&'a [u8]),
+	Unary(&'a [u8], &'a [u8]),
+	Binary(&'a [u8], &'a [u8], &'a [u8]),
+	Not(Box<CondExpr<'a>>),
+	And(Box<CondExpr<'a>>, Box<CondExpr<'a>>),
+	Or(Box<CondExpr<'a>>, Box<CondExpr<'a>>),
+}
+
+#[derive(Debug)]
+pub enum Command<'a> {
+	Simple(SimpleCommand<'a>),
+	Compound(CompoundCommand<'a>, Vec<Redirect<'a>>),
+}
+
 #[derive(Debug)]
 pub struct Pipeline<'a> {
 	pub commands: Vec<Command<'a>>,
+	pub is_negated: bool,
+	/// The source of the pipeline, such as for listing it as a job.
+	pub text: &'a [u8],
+	/// The source of each command.
+	pub command_texts: Vec<&'a [u8]>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum Connector { And, Or }
+
+#[derive(Debug)]
+pub struct AndOr<'a> {
+	pub first: Pipeline<'a>,
+	pub rest: Vec<(Connector, Pipeline<'a>)>,
 	pub is_background: bool,
+	/// The source of the list without the `&` or `;` that ends it.
+	pub text: &'a [u8],
+}
+
+#[derive(Debug)]
+pub struct List<'a> {
+	pub items: Vec<AndOr<'a>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseErrorKind {
+	/// A token that does not fit here, and a description of what was expected if there is one.
+	UnexpectedToken(Vec<u8>, Option<String>),
+	/// A missing command or command list, such as before `;` or `then`.
+	EmptyCommand,
+	/// A file descriptor number of a redirection that is out of range.
+	BadFdNumber,
+	/// The input ends in the middle of a command, which more input may complete. Holds what was expected.
+	Incomplete(String),
+}
+
+/// A parse error, with the span of the input where it was found.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+	pub kind: ParseErrorKind,
+	pub start: usize,
+	pub end: usize,
+}
+
+impl ParseError {
+	fn new(kind: ParseErrorKind, start: usize, end: usize) -> ParseError {
+		ParseError { kind: kind, start: start, end: end }
+	}
+
+	pub fn is_incomplete(&self) -> bool {
+		match self.kind {
+			ParseErrorKind::Incomplete(..) => true,
+			_ => false,
+		}
+	}
+
+	/// Returns the start and end of the line of `source` where the error is.
+	/// An error at the end of input belongs to the last line rather than the empty one after it.
+	fn line_bounds(&self, source: &[u8]) -> (usize, usize) {
+		let mut pos = std::cmp::min(self.start, source.len());
+		if pos == source.len() && pos > 0 && source[pos - 1] == b'\n' {
+			pos -= 1;
+		}
+		let start = source[.. pos].iter().rposition(|&c| c == b'\n').map_or(0, |n| n + 1);
+		let end = source[pos ..].iter().position(|&c| c == b'\n').map_or(source.len(), |n| pos + n);
+		(start, end)
+	}
+
+	/// Returns the number of the line of `source` where the error is, counting from 1.
+	pub fn line_number(&self, source: &[u8]) -> usize {
+		let (start, _) = self.line_bounds(source);
+		source[.. start].iter().filter(|&&c| c == b'\n').count() + 1
+	}
+
+	/// Returns the line of `source` where the error is, and below it a line with `^` under the fault.
+	pub fn caret(&self, source: &[u8]) -> String {
+		let (start, end) = self.line_bounds(source);
+		let line = &source[start .. end];
+		let column = std::cmp::min(self.start, end).saturating_sub(start);
+		// Tabs are kept so that the marker lines up however wide they are shown.
+		let mut marker: String = line[.. column].iter().map(|&c| if c == b'\t' { '\t' } else { ' ' }).collect();
+		let width = std::cmp::max(std::cmp::min(self.end, end).saturating_sub(self.start), 1);
+		marker.extend(std::iter::repeat('^').take(width));
+		format!("{}\n{}", String::from_utf8_lossy(line), marker)
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.kind {
+			ParseErrorKind::UnexpectedToken(ref token, ref expected) => {
+				match &token[..] {
+					b"\n" => write!(f, "unexpected newline")?,
+					_ => write!(f, "unexpected '{}'", String::from_utf8_lossy(token))?,
+				}
+				match *expected {
+					Some(ref expected) => write!(f, ", expecting {}", expected),
+					None => Ok(()),
+				}
+			},
+			ParseErrorKind::EmptyCommand => write!(f, "empty command"),
+			ParseErrorKind::BadFdNumber => write!(f, "bad file descriptor number"),
+			ParseErrorKind::Incomplete(ref expected) => write!(f, "unexpected end of input, expecting {}", expected),
+		}
+	}
+}
+impl error::Error for ParseError {
+	fn description(&self) -> &str {
+		match self.kind {
+			ParseErrorKind::UnexpectedToken(..) => "unexpected token",
+			ParseErrorKind::EmptyCommand => "empty command",
+			ParseErrorKind::BadFdNumber => "bad file descriptor number",
+			ParseErrorKind::Incomplete(..) => "incomplete input",
+		}
+	}
 }
 
-type ParseResult<T> = Result<T, String>;
+pub type ParseResult<T> = Result<T, ParseError>;
+
+const RESERVED_WORDS: &'static [&'static [u8]] = &[
+	b"if", b"then", b"elif", b"else", b"fi", b"while", b"until", b"do", b"done", b"{", b"}", b"!", b"[[", b"]]",
+];
 
 struct Parser<'a> {
 	line: &'a [u8],
@@ -32,6 +183,13 @@ struct Parser<'a> {
 }
 
 impl<'a> Parser<'a> {
+	/// Returns the source from `start` to the current position without the blanks at its end.
+	fn text_from(&self, start: usize) -> &'a [u8] {
+		let text = &self.line[start .. self.i];
+		let len = text.iter().rposition(|&c| !b" \t\n".contains(&c)).map_or(0, |n| n + 1);
+		&text[.. len]
+	}
+
 	fn proceed_while<F>(&mut self, f: F) where F: Fn(u8) -> bool {
 		while let Some(c) = self.line.get(self.i) {
 			if !f(*c) { break; }
@@ -39,17 +197,17 @@ impl<'a> Parser<'a> {
 		}
 	}
 
-	fn is_whitespace(c: u8) -> bool {
+	fn is_blank(c: u8) -> bool {
 		match c {
-			b' ' | b'\t' | b'\n' => true,
+			b' ' | b'\t' => true,
 			_ => false,
 		}
 	}
 
 	fn is_letter(c: u8) -> bool {
 		match c {
-			b'>' | b'<' | b'&' | b'|' => false,
-			_ => !Parser::is_whitespace(c),
+			b'>' | b'<' | b'&' | b'|' | b';' | b'\n' => false,
+			_ => !Parser::is_blank(c),
 		}
 	}
 
@@ -57,14 +215,163 @@ impl<'a> Parser<'a> {
 		 b'0' <= c && c <= b'9'
 	}
 
-	fn skip_whitespaces(&mut self) {
-		self.proceed_while(Parser::is_whitespace);
+	fn is_name_start(c: u8) -> bool {
+		c == b'_' || (b'a' <= c && c <= b'z') || (b'A' <= c && c <= b'Z')
+	}
+
+	fn is_assignment(word: &[u8]) -> bool {
+		match word.iter().position(|&c| c == b'=') {
+			Some(n) if n > 0 => Parser::is_name_start(word[0]) && word[.. n].iter().all(|&c| Parser::is_name_start(c) || Parser::is_digit(c)),
+			_ => false,
+		}
+	}
+
+	/// Whether a backslash and newline, which join two lines, start at `line[i]`.
+	fn is_line_continuation(&self, i: usize) -> bool {
+		self.line.get(i) == Some(&b'\\') && self.line.get(i + 1) == Some(&b'\n')
 	}
 
-	fn read_word(&mut self) -> &'a [u8] {
+	fn skip_blanks(&mut self) {
+		loop {
+			self.proceed_while(Parser::is_blank);
+			// A line continuation at the end of input is left to `read_word`, which reports it as incomplete.
+			if !self.is_line_continuation(self.i) || self.i + 2 == self.line.len() {
+				break;
+			}
+			self.i += 2;
+		}
+		if self.line.get(self.i) == Some(&b'#') {
+			self.proceed_while(|c| c != b'\n');
+		}
+	}
+
+	fn skip_linebreaks(&mut self) {
+		loop {
+			self.skip_blanks();
+			if self.line.get(self.i) != Some(&b'\n') { break; }
+			self.i += 1;
+		}
+	}
+
+	fn starts_with(&self, s: &[u8]) -> bool {
+		self.line[self.i ..].starts_with(s)
+	}
+
+	/// Returns the token at `i` as shown in error messages: a word, an operator or a single character.
+	fn token_at(&self, i: usize) -> &'a [u8] {
+		let line = self.line;
+		let end = line[i ..].iter().position(|&c| !Parser::is_letter(c)).map_or(line.len(), |n| i + n);
+		if end > i {
+			&line[i .. end]
+		} else if [&b"&&"[..], b"||", b">>"].iter().any(|op| line[i ..].starts_with(op)) {
+			&line[i .. i + 2]
+		} else {
+			&line[i .. std::cmp::min(i + 1, line.len())]
+		}
+	}
+
+	/// Reports the token at the current position, or the end of input, where `expected` should be.
+	fn unexpected(&self, expected: Option<String>) -> ParseError {
+		if self.i >= self.line.len() {
+			let expected = expected.unwrap_or("a command".to_string());
+			return ParseError::new(ParseErrorKind::Incomplete(expected), self.line.len(), self.line.len());
+		}
+		let token = self.token_at(self.i);
+		ParseError::new(ParseErrorKind::UnexpectedToken(token.to_vec(), expected), self.i, self.i + token.len())
+	}
+
+	/// Returns the next word if it is written without quotes, which is the only form a reserved word takes.
+	fn peek_plain_word(&self) -> Option<&'a [u8]> {
+		let end = self.line[self.i ..].iter().position(|&c| !Parser::is_letter(c)).map_or(self.line.len(), |n| self.i + n);
+		let word = &self.line[self.i .. end];
+		if word.is_empty() || word.iter().any(|&c| c == b'\\' || c == b'\'' || c == b'"') {
+			None
+		} else {
+			Some(word)
+		}
+	}
+
+	fn at_reserved(&self, words: &[&[u8]]) -> bool {
+		self.peek_plain_word().map_or(false, |w| words.contains(&w))
+	}
+
+	fn expect_reserved(&mut self, word: &[u8]) -> ParseResult<()> {
+		self.skip_linebreaks();
+		if self.at_reserved(&[word]) {
+			self.i += word.len();
+			Ok(())
+		} else {
+			Err(self.unexpected(Some(format!("'{}'", String::from_utf8_lossy(word)))))
+		}
+	}
+
+	fn skip_quoted(&mut self, quote: u8) -> ParseResult<()> {
 		let orig = self.i;
-		self.proceed_while(Parser::is_letter);
-		&self.line[orig .. self.i]
+		self.i += 1;
+		while let Some(&c) = self.line.get(self.i) {
+			if c == b'$' && quote == b'"' && self.line.get(self.i + 1) == Some(&b'(') {
+				self.i += 1;
+				self.skip_parens()?;
+				continue;
+			}
+			if c == b'`' && quote == b'"' {
+				self.skip_quoted(c)?;
+				continue;
+			}
+			self.i += 1;
+			if c == quote {
+				return Ok(());
+			} else if c == b'\\' && quote != b'\'' {
+				self.i += 1;
+			}
+		}
+		Err(ParseError::new(ParseErrorKind::Incomplete(format!("closing {}", quote as char)), orig, orig + 1))
+	}
+
+	/// Skips a parenthesized part of a word such as `$(( 1 + (2) ))`, starting at the open parenthesis.
+	fn skip_parens(&mut self) -> ParseResult<()> {
+		let orig = self.i;
+		let mut depth = 0;
+		while let Some(&c) = self.line.get(self.i) {
+			match c {
+				b'\\' => { self.i += 2; },
+				b'\'' | b'"' | b'`' => self.skip_quoted(c)?,
+				b'(' => {
+					depth += 1;
+					self.i += 1;
+				},
+				b')' => {
+					depth -= 1;
+					self.i += 1;
+					if depth == 0 {
+						return Ok(());
+					}
+				},
+				_ => { self.i += 1; },
+			}
+		}
+		Err(ParseError::new(ParseErrorKind::Incomplete("')'".to_string()), orig, orig + 1))
+	}
+
+	/// Reads a word as written in the source. Quotes are kept, so they are removed on expansion.
+	fn read_word(&mut self) -> ParseResult<&'a [u8]> {
+		let orig = self.i;
+		while let Some(&c) = self.line.get(self.i) {
+			match c {
+				b'\\' if self.i + 1 == self.line.len() || (self.is_line_continuation(self.i) && self.i + 2 == self.line.len()) => {
+					return Err(ParseError::new(ParseErrorKind::Incomplete("the next line".to_string()), self.i, self.i + 1));
+				},
+				b'\\' => { self.i = std::cmp::min(self.i + 2, self.line.len()); },
+				b'\'' | b'"' | b'`' => self.skip_quoted(c)?,
+				b'$' | b'<' | b'>' if self.line.get(self.i + 1) == Some(&b'(') => {
+					self.i += 1;
+					self.skip_parens()?;
+				},
+				_ if Parser::is_letter(c) => { self.i += 1; },
+				_ => { break; },
+			}
+		}
+		Ok(&self.line[orig .. self.i])
 	}
 
 	fn read_number(&mut self) -> Option<Result<i32, std::num::ParseIntError>> {
@@ -100,17 +407,25 @@ impl<'a> Parser<'a> {
 				return Ok(None);
 			},
 		};
+		// `<(` and `>(` start a process substitution, which is a word.
+		if self.line.get(self.i) == Some(&b'(') {
+			self.i = orig;
+			return Ok(None);
+		}
 
 		let from = match num {
 			None => if typ == RedirectType::Input { 0 } else { 1 },
 			Some(Ok(n)) => n,
-			Some(Err(e)) => { return Err(e.description().to_string()); },
+			Some(Err(_)) => {
+				let end = self.line[orig ..].iter().position(|&c| !Parser::is_digit(c)).map_or(self.line.len(), |n| orig + n);
+				return Err(ParseError::new(ParseErrorKind::BadFdNumber, orig, end));
+			},
 		};
 
-		self.skip_whitespaces();
-		let target = self.read_word();
+		self.skip_blanks();
+		let target = self.read_word()?;
 		if target.is_empty() {
-			return Err("empty redirect".to_string());
+			return Err(self.unexpected(Some("a redirect target".to_string())));
 		}
 
 		Ok(Some(Redirect { target: target, from: from, typ: typ }))
@@ -118,77 +433,376 @@ impl<'a> Parser<'a> {
 
 	fn parse_and_append_redirects(&mut self, redirects: &mut Vec<Redirect<'a>>) -> ParseResult<()> {
 		loop {
+			self.skip_blanks();
 			match self.parse_redirect() {
 				Ok(Some(redirect)) => redirects.push(redirect),
 				Ok(None) => { break; },
 				Err(e) => { return Err(e); },
 			}
-			self.skip_whitespaces();
 		}
 		Ok(())
 	}
 
-	fn parse_command(&mut self) -> ParseResult<Command<'a>> {
-		let mut redirects: Vec<Redirect<'a>> = vec![];
+	fn parse_simple_command(&mut self) -> ParseResult<SimpleCommand<'a>> {
+		let mut assignments: Vec<&'a [u8]> = vec![];
+		let mut name: &'a [u8] = &[];
 		let mut arguments: Vec<&'a [u8]> = vec![];
+		let mut redirects: Vec<Redirect<'a>> = vec![];
 
-		if let Err(e) = self.parse_and_append_redirects(&mut redirects) {
-			return Err(e);
+		loop {
+			self.parse_and_append_redirects(&mut redirects)?;
+			let word = self.read_word()?;
+			if word.is_empty() {
+				break;
+			} else if !name.is_empty() {
+				arguments.push(word);
+			} else if Parser::is_assignment(word) {
+				assignments.push(word);
+			} else {
+				name = word;
+			}
 		}
 
-		let name = self.read_word();
-		if name.is_empty() {
-			return Err("empty command".to_string());
+		if name.is_empty() && assignments.is_empty() && redirects.is_empty() {
+			// At the end of input, this follows `|`, `&&`, `||` or `!`.
+			return Err(match self.line.get(self.i) {
+				Some(_) => ParseError::new(ParseErrorKind::EmptyCommand, self.i, self.i + self.token_at(self.i).len()),
+				None => self.unexpected(None),
+			});
 		}
+		Ok(SimpleCommand { assignments: assignments, name: name, arguments: arguments, redirects: redirects })
+	}
+
+	fn parse_if(&mut self) -> ParseResult<CompoundCommand<'a>> {
+		let mut conditions: Vec<(List<'a>, List<'a>)> = vec![];
+		let mut otherwise = None;
 
+		self.expect_reserved(b"if")?;
 		loop {
-			self.skip_whitespaces();
-			let word = self.read_word();
-			if word.is_empty() {
-				break;
+			let condition = self.parse_list(&[b"then"])?;
+			self.expect_reserved(b"then")?;
+			let body = self.parse_list(&[b"elif", b"else", b"fi"])?;
+			conditions.push((condition, body));
+			self.skip_linebreaks();
+			if self.at_reserved(&[b"elif"]) {
+				self.i += 4;
 			} else {
-				arguments.push(word);
+				break;
+			}
+		}
+		if self.at_reserved(&[b"else"]) {
+			self.i += 4;
+			otherwise = Some(self.parse_list(&[b"fi"])?);
+		}
+		self.expect_reserved(b"fi")?;
+		Ok(CompoundCommand::If { conditions: conditions, otherwise: otherwise })
+	}
+
+	fn parse_while(&mut self) -> ParseResult<CompoundCommand<'a>> {
+		let is_until = self.at_reserved(&[b"until"]);
+		self.expect_reserved(if is_until { b"until" } else { b"while" })?;
+		let condition = self.parse_list(&[b"do"])?;
+		self.expect_reserved(b"do")?;
+		let body = self.parse_list(&[b"done"])?;
+		self.expect_reserved(b"done")?;
+		Ok(CompoundCommand::While { is_until: is_until, condition: condition, body: body })
+	}
+
+	fn parse_brace_group(&mut self) -> ParseResult<CompoundCommand<'a>> {
+		self.expect_reserved(b"{")?;
+		let body = self.parse_list(&[b"}"])?;
+		self.expect_reserved(b"}")?;
+		Ok(CompoundCommand::BraceGroup(body))
+	}
+
+	fn parse_arithmetic(&mut self) -> ParseResult<CompoundCommand<'a>> {
+		let orig = self.i;
+		self.skip_parens()?;
+		if self.line[orig + 1 .. self.i].last() != Some(&b')') {
+			return Err(ParseError::new(ParseErrorKind::UnexpectedToken(b")".to_vec(), Some("'))'".to_string())), self.i - 1, self.i));
+		}
+		Ok(CompoundCommand::Arithmetic(&self.line[orig + 2 .. self.i - 2]))
+	}
+
+	/// Reads a token inside `[[ ]]`. The right operand of `=~` may contain parentheses and `|`.
+	fn read_cond_token(&mut self, is_regex: bool) -> ParseResult<&'a [u8]> {
+		self.skip_linebreaks();
+		let orig = self.i;
+		if self.starts_with(b"&&") || self.starts_with(b"||") {
+			self.i += 2;
+			return Ok(&self.line[orig .. self.i]);
+		}
+		match self.line.get(self.i) {
+			Some(&b'(') | Some(&b')') | Some(&b'<') | Some(&b'>') if !is_regex => {
+				self.i += 1;
+				return Ok(&self.line[orig .. self.i]);
+			},
+			_ => {},
+		}
+		let mut depth = 0;
+		while let Some(&c) = self.line.get(self.i) {
+			match c {
+				b'\\' => { self.i = std::cmp::min(self.i + 2, self.line.len()); },
+				b'\'' | b'"' | b'`' => self.skip_quoted(c)?,
+				b'$' if self.line.get(self.i + 1) == Some(&b'(') => {
+					self.i += 1;
+					self.skip_parens()?;
+				},
+				b'(' | b'|' if is_regex => {
+					if c == b'(' { depth += 1; }
+					self.i += 1;
+				},
+				b')' if is_regex && depth > 0 => {
+					depth -= 1;
+					self.i += 1;
+				},
+				b'(' | b')' => { break; },
+				_ if Parser::is_letter(c) => { self.i += 1; },
+				_ if depth > 0 && Parser::is_blank(c) => { self.i += 1; },
+				_ => { break; },
+			}
+		}
+		Ok(&self.line[orig .. self.i])
+	}
+
+	fn peek_cond_token(&mut self) -> ParseResult<&'a [u8]> {
+		let orig = self.i;
+		let token = self.read_cond_token(false);
+		self.i = orig;
+		token
+	}
+
+	/// Reports the `[[ ]]` token just read, or what follows when it is empty, where `expected` should be.
+	fn unexpected_cond_token(&mut self, token: &[u8], expected: &str) -> ParseError {
+		if token.is_empty() {
+			let expected = if self.i == self.line.len() { "']]'" } else { expected };
+			return self.unexpected(Some(expected.to_string()));
+		}
+		ParseError::new(ParseErrorKind::UnexpectedToken(token.to_vec(), Some(expected.to_string())), self.i - token.len(), self.i)
+	}
+
+	fn expect_cond_operand(&mut self, is_regex: bool) -> ParseResult<&'a [u8]> {
+		match self.read_cond_token(is_regex)? {
+			token @ b"" | token @ b"]]" | token @ b"&&" | token @ b"||" | token @ b"(" | token @ b")" => {
+				Err(self.unexpected_cond_token(token, "an operand in conditional expression"))
+			},
+			token => Ok(token),
+		}
+	}
+
+	fn parse_cond_or(&mut self) -> ParseResult<CondExpr<'a>> {
+		let mut expr = self.parse_cond_and()?;
+		while self.peek_cond_token()? == b"||" {
+			self.read_cond_token(false)?;
+			expr = CondExpr::Or(Box::new(expr), Box::new(self.parse_cond_and()?));
+		}
+		Ok(expr)
+	}
+
+	fn parse_cond_and(&mut self) -> ParseResult<CondExpr<'a>> {
+		let mut expr = self.parse_cond_not()?;
+		while self.peek_cond_token()? == b"&&" {
+			self.read_cond_token(false)?;
+			expr = CondExpr::And(Box::new(expr), Box::new(self.parse_cond_not()?));
+		}
+		Ok(expr)
+	}
+
+	fn parse_cond_not(&mut self) -> ParseResult<CondExpr<'a>> {
+		if self.peek_cond_token()? == b"!" {
+			self.read_cond_token(false)?;
+			return Ok(CondExpr::Not(Box::new(self.parse_cond_not()?)));
+		}
+		self.parse_cond_primary()
+	}
+
+	fn parse_cond_primary(&mut self) -> ParseResult<CondExpr<'a>> {
+		if self.peek_cond_token()? == b"(" {
+			self.read_cond_token(false)?;
+			let expr = self.parse_cond_or()?;
+			match self.read_cond_token(false)? {
+				b")" => {},
+				token => { return Err(self.unexpected_cond_token(token, "')'")); },
 			}
+			return Ok(expr);
 		}
+		let word = self.expect_cond_operand(false)?;
+		if cond::is_unary_operator(word) {
+			match self.peek_cond_token()? {
+				b"" | b"]]" | b"&&" | b"||" | b")" => {},
+				_ => { return Ok(CondExpr::Unary(word, self.expect_cond_operand(false)?)); },
+			}
+		}
+		let op = self.peek_cond_token()?;
+		if cond::is_binary_operator(op) || op == b"=~" {
+			self.read_cond_token(false)?;
+			let right = self.expect_cond_operand(op == b"=~")?;
+			return Ok(CondExpr::Binary(word, op, right));
+		}
+		Ok(CondExpr::Word(word))
+	}
 
-		if let Err(e) = self.parse_and_append_redirects(&mut redirects) {
-			return Err(e);
+	fn parse_conditional(&mut self) -> ParseResult<CompoundCommand<'a>> {
+		self.expect_reserved(b"[[")?;
+		let expr = self.parse_cond_or()?;
+		match self.read_cond_token(false)? {
+			b"]]" => Ok(CompoundCommand::Conditional(expr)),
+			token => Err(self.unexpected_cond_token(token, "']]'")),
 		}
+	}
 
-		Ok(Command { name: name, arguments: arguments, redirects: redirects })
+	fn parse_command(&mut self) -> ParseResult<Command<'a>> {
+		self.skip_blanks();
+		if self.starts_with(b"((") {
+			let compound = self.parse_arithmetic()?;
+			let mut redirects: Vec<Redirect<'a>> = vec![];
+			self.parse_and_append_redirects(&mut redirects)?;
+			return Ok(Command::Compound(compound, redirects));
+		}
+		let compound = match self.peek_plain_word() {
+			Some(b"if") => self.parse_if()?,
+			Some(b"while") | Some(b"until") => self.parse_while()?,
+			Some(b"{") => self.parse_brace_group()?,
+			Some(b"[[") => self.parse_conditional()?,
+			Some(w) if RESERVED_WORDS.contains(&w) => {
+				return Err(self.unexpected(None));
+			},
+			_ => { return Ok(Command::Simple(self.parse_simple_command()?)); },
+		};
+		let mut redirects: Vec<Redirect<'a>> = vec![];
+		self.parse_and_append_redirects(&mut redirects)?;
+		Ok(Command::Compound(compound, redirects))
 	}
 
 	fn parse_pipeline(&mut self) -> ParseResult<Pipeline<'a>> {
 		let mut commands: Vec<Command<'a>> = vec![];
-		let mut is_background = false;
+		let mut command_texts: Vec<&'a [u8]> = vec![];
+		let mut is_negated = false;
 
+		self.skip_blanks();
+		let start = self.i;
+		if self.at_reserved(&[b"!"]) {
+			self.i += 1;
+			is_negated = true;
+		}
 		loop {
-			self.skip_whitespaces();
-			match self.parse_command() {
-				Ok(command) => commands.push(command),
-				Err(e) => { return Err(e); },
+			self.skip_blanks();
+			let command_start = self.i;
+			commands.push(self.parse_command()?);
+			command_texts.push(self.text_from(command_start));
+			self.skip_blanks();
+			if self.starts_with(b"|") && !self.starts_with(b"||") {
+				self.i += 1;
+				self.skip_linebreaks();
+			} else {
+				break;
 			}
+		}
+		Ok(Pipeline { commands: commands, is_negated: is_negated, text: self.text_from(start), command_texts: command_texts })
+	}
+
+	fn parse_and_or(&mut self) -> ParseResult<AndOr<'a>> {
+		self.skip_blanks();
+		let start = self.i;
+		let first = self.parse_pipeline()?;
+		let mut rest: Vec<(Connector, Pipeline<'a>)> = vec![];
+
+		loop {
+			self.skip_blanks();
+			let connector = if self.starts_with(b"&&") {
+				Connector::And
+			} else if self.starts_with(b"||") {
+				Connector::Or
+			} else {
+				break;
+			};
+			self.i += 2;
+			self.skip_linebreaks();
+			rest.push((connector, self.parse_pipeline()?));
+		}
+		Ok(AndOr { first: first, rest: rest, is_background: false, text: self.text_from(start) })
+	}
+
+	/// Parses and-or lists up to one of `terminators` or the end of input.
+	fn parse_list(&mut self, terminators: &[&[u8]]) -> ParseResult<List<'a>> {
+		let mut items: Vec<AndOr<'a>> = vec![];
+
+		loop {
+			self.skip_linebreaks();
+			if self.i == self.line.len() || self.at_reserved(terminators) {
+				break;
+			}
+			let mut and_or = self.parse_and_or()?;
+			self.skip_blanks();
 			match self.line.get(self.i) {
-				Some(&b'|') => { self.i += 1; },
+				Some(&b';') | Some(&b'\n') => { self.i += 1; },
 				Some(&b'&') => {
 					self.i += 1;
-					is_background = true;
-					self.skip_whitespaces();
-					if let Some(&c) = self.line.get(self.i) {
-						return Err(format!("character after '&': '{}'", c as char));
-					} else {
-						break;
-					}
+					and_or.is_background = true;
 				},
-				Some(&c) => { return Err(format!("unknown command separator: '{}'", c as char)); },
-				None => { break; },
+				Some(_) => { return Err(self.unexpected(None)); },
+				None => {},
 			}
+			items.push(and_or);
 		}
-		Ok(Pipeline { commands: commands, is_background: is_background })
+		if items.is_empty() && !terminators.is_empty() {
+			if self.i == self.line.len() {
+				return Err(self.unexpected(Some(describe_alternatives(terminators))));
+			}
+			return Err(ParseError::new(ParseErrorKind::EmptyCommand, self.i, self.i + self.token_at(self.i).len()));
+		}
+		Ok(List { items: items })
+	}
+
+	/// Parses and-or lists up to the end of the current line.
+	fn parse_complete_command(&mut self) -> ParseResult<List<'a>> {
+		let mut items: Vec<AndOr<'a>> = vec![];
+
+		self.skip_linebreaks();
+		while self.i < self.line.len() {
+			let mut and_or = self.parse_and_or()?;
+			self.skip_blanks();
+			let separator = self.line.get(self.i).cloned();
+			match separator {
+				Some(b';') | Some(b'\n') => { self.i += 1; },
+				Some(b'&') => {
+					self.i += 1;
+					and_or.is_background = true;
+				},
+				Some(_) => { return Err(self.unexpected(None)); },
+				None => {},
+			}
+			items.push(and_or);
+			self.skip_blanks();
+			if separator == Some(b'\n') || self.line.get(self.i) == Some(&b'\n') {
+				break;
+			}
+		}
+		Ok(List { items: items })
 	}
 }
 
-pub fn parse<'a>(line: &'a [u8]) -> ParseResult<Pipeline<'a>> {
+/// Quotes the reserved words and joins them as in "'elif', 'else' or 'fi'".
+fn describe_alternatives(words: &[&[u8]]) -> String {
+	let quoted: Vec<String> = words.iter().map(|word| format!("'{}'", String::from_utf8_lossy(word))).collect();
+	match quoted.split_last() {
+		Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+		_ => quoted.join(""),
+	}
+}
+
+pub fn parse<'a>(line: &'a [u8]) -> ParseResult<List<'a>> {
 	let mut parser: Parser<'a> = Parser { line: line, i: 0 };
-	parser.parse_pipeline()
+	let list = parser.parse_list(&[])?;
+	if parser.i != line.len() {
+		return Err(parser.unexpected(None));
+	}
+	Ok(list)
+}
+
+/// Parses the next line of a script starting from `start`, and returns it with the position of what follows.
+pub fn parse_next<'a>(script: &'a [u8], start: usize) -> ParseResult<(List<'a>, usize)> {
+	let mut parser: Parser<'a> = Parser { line: script, i: start };
+	let list = parser.parse_complete_command()?;
+	Ok((list, parser.i))
 }
@@ -0,0 +1,384 @@
+//! Formatting for the `printf` builtin, also used by `echo -e` for backslash escapes.
+
+use error;
+
+use std::io;
+use std::ffi::CString;
+use libc;
+
+fn is_octal(c: u8) -> bool {
+	b'0' <= c && c <= b'7'
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+	match c {
+		b'0' ... b'9' => Some(c - b'0'),
+		b'a' ... b'f' => Some(c - b'a' + 10),
+		b'A' ... b'F' => Some(c - b'A' + 10),
+		_ => None,
+	}
+}
+
+/// Decodes the escape sequence after the backslash at `s[*i - 1]` and advances `i` past it.
+/// In an argument of `%b` or `echo -e`, octal escapes are written `\0nnn` and `\c` stops the output,
+/// which is reported by returning `true`.
+pub fn decode_escape(s: &[u8], i: &mut usize, out: &mut Vec<u8>, is_argument: bool) -> bool {
+	let c = match s.get(*i) {
+		Some(&c) => c,
+		None => {
+			out.push(b'\\');
+			return false;
+		},
+	};
+	*i += 1;
+	match c {
+		b'\\' => out.push(b'\\'),
+		b'a' => out.push(0x07),
+		b'b' => out.push(0x08),
+		b'e' | b'E' => out.push(0x1b),
+		b'f' => out.push(0x0c),
+		b'n' => out.push(b'\n'),
+		b'r' => out.push(b'\r'),
+		b't' => out.push(b'\t'),
+		b'v' => out.push(0x0b),
+		b'"' | b'\'' if !is_argument => out.push(c),
+		b'c' if is_argument => { return true; },
+		b'x' if s.get(*i).and_then(|&c| hex_value(c)).is_some() => {
+			let mut n = 0u8;
+			for _ in 0 .. 2 {
+				match s.get(*i).and_then(|&c| hex_value(c)) {
+					Some(v) => {
+						n = n.wrapping_mul(16).wrapping_add(v);
+						*i += 1;
+					},
+					None => { break; },
+				}
+			}
+			out.push(n);
+		},
+		_ if is_octal(c) && (!is_argument || c == b'0') => {
+			let mut n = if is_argument { 0u8 } else { c - b'0' };
+			for _ in 0 .. if is_argument { 3 } else { 2 } {
+				match s.get(*i) {
+					Some(&d) if is_octal(d) => {
+						n = n.wrapping_mul(8).wrapping_add(d - b'0');
+						*i += 1;
+					},
+					_ => { break; },
+				}
+			}
+			out.push(n);
+		},
+		_ => {
+			out.push(b'\\');
+			out.push(c);
+		},
+	}
+	false
+}
+
+/// Decodes the backslash escapes of an argument of `%b` or `echo -e`. Returns `true` when `\c` was found.
+pub fn decode_argument(s: &[u8], out: &mut Vec<u8>) -> bool {
+	let mut i = 0;
+	while let Some(&c) = s.get(i) {
+		i += 1;
+		if c != b'\\' {
+			out.push(c);
+		} else if decode_escape(s, &mut i, out, true) {
+			return true;
+		}
+	}
+	false
+}
+
+fn is_safe(c: u8) -> bool {
+	match c {
+		b'a' ... b'z' | b'A' ... b'Z' | b'0' ... b'9' => true,
+		b'_' | b'.' | b'/' | b':' | b'=' | b'+' | b',' | b'@' | b'%' | b'-' => true,
+		_ => false,
+	}
+}
+
+/// Quotes a string so that the shell reads it back as a single word.
+pub fn quote(s: &[u8]) -> Vec<u8> {
+	if !s.is_empty() && s.iter().all(|&c| is_safe(c)) {
+		return s.to_vec();
+	}
+	let mut quoted = vec![b'\''];
+	for &c in s {
+		if c == b'\'' {
+			quoted.extend(b"'\\''");
+		} else {
+			quoted.push(c);
+		}
+	}
+	quoted.push(b'\'');
+	quoted
+}
+
+/// Formats with `snprintf`, which fails when the output would be longer than a `c_int` can count.
+fn c_format_int(spec: &[u8], n: i64) -> io::Result<Vec<u8>> {
+	let spec = CString::new(spec).unwrap();
+	unsafe{
+		let len = libc::snprintf(::std::ptr::null_mut(), 0, spec.as_ptr(), n as libc::c_longlong);
+		if len < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let mut buf = vec![0u8; len as usize + 1];
+		libc::snprintf(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), spec.as_ptr(), n as libc::c_longlong);
+		buf.truncate(len as usize);
+		Ok(buf)
+	}
+}
+
+fn c_format_float(spec: &[u8], x: f64) -> io::Result<Vec<u8>> {
+	let spec = CString::new(spec).unwrap();
+	unsafe{
+		let len = libc::snprintf(::std::ptr::null_mut(), 0, spec.as_ptr(), x as libc::c_double);
+		if len < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let mut buf = vec![0u8; len as usize + 1];
+		libc::snprintf(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), spec.as_ptr(), x as libc::c_double);
+		buf.truncate(len as usize);
+		Ok(buf)
+	}
+}
+
+struct Formatter<'a, 'b: 'a> {
+	args: &'a [&'b [u8]],
+	next: usize,
+	out: Vec<u8>,
+	errors: Vec<String>,
+	stop: bool,
+}
+
+impl<'a, 'b> Formatter<'a, 'b> {
+	fn next_arg(&mut self) -> &'b [u8] {
+		match self.args.get(self.next) {
+			Some(&arg) => {
+				self.next += 1;
+				arg
+			},
+			None => b"",
+		}
+	}
+
+	/// Returns the value of a leading quote as in `'a`, which POSIX defines as the code of the character.
+	fn char_code(arg: &[u8]) -> Option<i64> {
+		match arg.first() {
+			Some(&b'\'') | Some(&b'"') => Some(arg.get(1).map_or(0, |&c| c as i64)),
+			_ => None,
+		}
+	}
+
+	fn integer(&mut self, arg: &[u8]) -> i64 {
+		if let Some(n) = Formatter::char_code(arg) {
+			return n;
+		}
+		let s = String::from_utf8_lossy(arg);
+		let s = s.trim_start();
+		if s.is_empty() {
+			return 0;
+		}
+		let (negative, digits) = match s.as_bytes()[0] {
+			b'-' => (true, &s[1 ..]),
+			b'+' => (false, &s[1 ..]),
+			_ => (false, s),
+		};
+		let r = if digits.starts_with("0x") || digits.starts_with("0X") {
+			i64::from_str_radix(&digits[2 ..], 16)
+		} else if digits.len() > 1 && digits.starts_with('0') {
+			i64::from_str_radix(&digits[1 ..], 8)
+		} else {
+			digits.parse()
+		};
+		match r {
+			Ok(n) => if negative { n.wrapping_neg() } else { n },
+			Err(_) => {
+				self.errors.push(format!("{}: invalid number", String::from_utf8_lossy(arg)));
+				0
+			},
+		}
+	}
+
+	fn float(&mut self, arg: &[u8]) -> f64 {
+		if let Some(n) = Formatter::char_code(arg) {
+			return n as f64;
+		}
+		let s = String::from_utf8_lossy(arg);
+		let s = s.trim();
+		if s.is_empty() {
+			return 0.0;
+		}
+		s.parse().unwrap_or_else(|_| {
+			self.errors.push(format!("{}: invalid number", s));
+			0.0
+		})
+	}
+
+	fn pad(&mut self, s: &[u8], width: usize, left: bool) {
+		if !left {
+			self.out.extend(::std::iter::repeat(b' ').take(width.saturating_sub(s.len())));
+		}
+		self.out.extend(s);
+		if left {
+			self.out.extend(::std::iter::repeat(b' ').take(width.saturating_sub(s.len())));
+		}
+	}
+
+	fn c_output(&mut self, spec: &[u8], r: io::Result<Vec<u8>>) {
+		match r {
+			Ok(s) => self.out.extend(s),
+			Err(e) => self.errors.push(format!("{}: {}", String::from_utf8_lossy(spec), error::describe_io(&e))),
+		}
+	}
+
+	/// Reads a width or precision, either digits or `*` taking the next argument.
+	/// A count that does not fit in a `c_int` is reported as `invalid`, and stops the output.
+	fn read_count(&mut self, format: &[u8], i: &mut usize, invalid: &str) -> Option<i64> {
+		let (count, n) = if format.get(*i) == Some(&b'*') {
+			*i += 1;
+			let arg = self.next_arg();
+			(arg, self.integer(arg))
+		} else {
+			let start = *i;
+			while format.get(*i).map_or(false, |c| c.is_ascii_digit()) {
+				*i += 1;
+			}
+			if start == *i {
+				return None;
+			}
+			let count = &format[start .. *i];
+			(count, String::from_utf8_lossy(count).parse().unwrap_or(i64::max_value()))
+		};
+		if n.checked_abs().map_or(true, |n| n > libc::c_int::max_value() as i64) {
+			self.errors.push(format!("{}: {}", String::from_utf8_lossy(count), invalid));
+			self.stop = true;
+			return None;
+		}
+		Some(n)
+	}
+
+	/// Formats one conversion specification, whose `%` is at `format[*i - 1]`.
+	fn conversion(&mut self, format: &[u8], i: &mut usize) {
+		let start = *i - 1;
+		let mut flags = vec![];
+		while let Some(&c) = format.get(*i) {
+			if !b"-+ #0".contains(&c) { break; }
+			flags.push(c);
+			*i += 1;
+		}
+		let mut width = self.read_count(format, i, "invalid field width");
+		if width.map_or(false, |w| w < 0) {
+			flags.push(b'-');
+			width = width.map(|w| -w);
+		}
+		let precision = if format.get(*i) == Some(&b'.') {
+			*i += 1;
+			Some(self.read_count(format, i, "invalid precision").unwrap_or(0)).filter(|&p| p >= 0)
+		} else {
+			None
+		};
+		if self.stop {
+			return;
+		}
+		while format.get(*i).map_or(false, |c| b"hlLjzt".contains(c)) {
+			*i += 1;
+		}
+		let conversion = match format.get(*i) {
+			Some(&c) => c,
+			None => {
+				self.errors.push(format!("{}: missing format character", String::from_utf8_lossy(&format[start ..])));
+				self.stop = true;
+				return;
+			},
+		};
+		*i += 1;
+
+		let mut spec = vec![b'%'];
+		spec.extend(&flags);
+		if let Some(w) = width {
+			spec.extend(w.to_string().into_bytes());
+		}
+		if let Some(p) = precision {
+			spec.push(b'.');
+			spec.extend(p.to_string().into_bytes());
+		}
+		let width = width.unwrap_or(0) as usize;
+		let left = flags.contains(&b'-');
+		match conversion {
+			b'd' | b'i' | b'o' | b'u' | b'x' | b'X' => {
+				let arg = self.next_arg();
+				let n = self.integer(arg);
+				spec.extend(b"ll");
+				spec.push(conversion);
+				self.c_output(&spec, c_format_int(&spec, n));
+			},
+			b'f' | b'F' | b'e' | b'E' | b'g' | b'G' | b'a' | b'A' => {
+				let arg = self.next_arg();
+				let x = self.float(arg);
+				spec.push(conversion);
+				self.c_output(&spec, c_format_float(&spec, x));
+			},
+			b'c' => {
+				let arg = self.next_arg();
+				let s = arg.get(0 .. 1).unwrap_or(b"");
+				self.pad(s, width, left);
+			},
+			b's' | b'b' | b'q' => {
+				let arg = self.next_arg();
+				let mut s = match conversion {
+					b's' => arg.to_vec(),
+					b'q' => quote(arg),
+					_ => {
+						let mut s = vec![];
+						self.stop = decode_argument(arg, &mut s);
+						s
+					},
+				};
+				if let Some(p) = precision {
+					s.truncate(p as usize);
+				}
+				self.pad(&s, width, left);
+			},
+			_ => {
+				self.errors.push(format!("{}: invalid conversion specification", String::from_utf8_lossy(&format[start .. *i])));
+				self.stop = true;
+			},
+		}
+	}
+
+	fn format_once(&mut self, format: &[u8]) {
+		let mut i = 0;
+		while let Some(&c) = format.get(i) {
+			if self.stop {
+				return;
+			}
+			i += 1;
+			match c {
+				b'\\' => { decode_escape(format, &mut i, &mut self.out, false); },
+				b'%' if format.get(i) == Some(&b'%') => {
+					self.out.push(b'%');
+					i += 1;
+				},
+				b'%' => self.conversion(format, &mut i),
+				_ => self.out.push(c),
+			}
+		}
+	}
+}
+
+/// Formats `args` with `format`, which is reused while arguments remain.
+/// Returns the output and the errors about invalid arguments.
+pub fn format(format: &[u8], args: &[&[u8]]) -> (Vec<u8>, Vec<String>) {
+	let mut formatter = Formatter { args: args, next: 0, out: vec![], errors: vec![], stop: false };
+	loop {
+		let start = formatter.next;
+		formatter.format_once(format);
+		if formatter.stop || formatter.next >= args.len() || formatter.next == start {
+			break;
+		}
+	}
+	(formatter.out, formatter.errors)
+}
@@ -1,17 +1,17 @@
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CString,OsStr};
 use std::{env,fs,io};
 
 pub struct SearchCache {
 	imp: HashMap<CString, CString>
 }
 
-const PATH_KEY: &'static str = "PATH";
+pub const PATH_KEY: &'static [u8] = b"PATH";
 
 impl SearchCache {
-	pub fn new() -> SearchCache {
+	pub fn new(path: Option<&[u8]>) -> SearchCache {
 		let mut this = SearchCache { imp: HashMap::new() };
-		this.rehash();
+		this.rehash(path);
 		this
 	}
 	fn add_entry(&mut self, entry: io::Result<fs::DirEntry>) -> io::Result<()> {
@@ -22,10 +22,12 @@ impl SearchCache {
 		self.imp.entry(file_name).or_insert(path);
 		Ok(())
 	}
-	pub fn rehash(&mut self) {
+	/// Finds the commands in the directories of `path`, the value of the shell's `PATH`.
+	pub fn rehash(&mut self, path: Option<&[u8]>) {
+		use std::os::unix::ffi::OsStrExt;
 		self.imp.clear();
-		if let Some(ref paths) = env::var_os(PATH_KEY) {
-			for path in env::split_paths(paths) {
+		if let Some(paths) = path {
+			for path in env::split_paths(OsStr::from_bytes(paths)) {
 				if let Ok(entries) = fs::read_dir(path) {
 					for entry in entries {
 						let _ = self.add_entry(entry);
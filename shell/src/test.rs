@@ -1,4 +1,4 @@
-use std::{fs,env,io,process};
+use std::{fs,env,io,path,process};
 use io::prelude::*;
 use std::str;
 
@@ -14,9 +14,10 @@ fn sleep_little() {
 }
 
 impl TmuxHandler {
-	pub fn new() -> io::Result<TmuxHandler> {
+	/// Starts ish in a tmux server of its own, named after the test.
+	pub fn new(name: &str) -> io::Result<TmuxHandler> {
 		let mut cmd = process::Command::new("tmux");
-		let child = cmd.arg("-C")
+		let child = cmd.args(&["-L", &format!("ish-test-{}", name), "-C"])
 			.stdin(process::Stdio::piped())
 			.stdout(process::Stdio::piped())
 			.spawn()?;
@@ -67,6 +68,8 @@ impl TmuxHandler {
 		let mut buf: Vec<u8> = b"send-keys ".to_vec();
 		for &c in content {
 			match c {
+				// Characters that are special in tmux commands.
+				b';' | b'{' | b'}' => { buf.push(b'\\'); buf.push(c); },
 				0x21...0x7e => buf.push(c),
 				b' ' => buf.extend(b"Space"),
 				_ => panic!("unknown char {}", c),
@@ -83,8 +86,583 @@ impl TmuxHandler {
 	}
 }
 
+impl Drop for TmuxHandler {
+	fn drop(&mut self) {
+		self.send(b"kill-server");
+		// Wait for the client to exit, so that the server is gone before the test ends.
+		let _ = io::copy(&mut self.stdout, &mut io::sink());
+	}
+}
+
 #[test]
 fn prompt() {
-	let mut t = TmuxHandler::new().unwrap();
+	let mut t = TmuxHandler::new("prompt").unwrap();
 	assert_eq!(t.capture(), b"ish>");
 }
+
+fn run_script(name: &str, script: &[u8]) -> process::Output {
+	let path = env::temp_dir().join(name);
+	fs::File::create(&path).unwrap().write_all(script).unwrap();
+	process::Command::new("target/debug/ish").arg(&path).output().unwrap()
+}
+
+#[test]
+fn script_without_shebang() {
+	use std::os::unix::fs::PermissionsExt;
+	let path = env::temp_dir().join("ish-test-noexec");
+	fs::File::create(&path).unwrap().write_all(b"# no shebang\necho hello\n").unwrap();
+	fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+	let mut script = path.to_str().unwrap().as_bytes().to_vec();
+	script.extend(b"\n/nonexistent/command\n");
+	let out = run_script("ish-test-script-without-shebang", &script);
+	assert_eq!(out.stdout, b"hello\n");
+	assert_eq!(out.status.code(), Some(127));
+}
+
+#[test]
+fn signaled_status() {
+	use std::os::unix::fs::PermissionsExt;
+	let path = env::temp_dir().join("ish-test-die");
+	fs::File::create(&path).unwrap().write_all(b"#!/bin/sh\nkill -KILL $$\n").unwrap();
+	fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+	let out = run_script("ish-test-signaled-status", path.to_str().unwrap().as_bytes());
+	assert_eq!(out.stderr, b"Killed\n");
+	assert_eq!(out.status.code(), Some(137));
+}
+
+#[test]
+fn pipefail() {
+	let out = run_script("ish-test-pipefail", b"false | true\n\
+		echo $? ${PIPESTATUS[@]}\n\
+		set -o pipefail\n\
+		false | true\n\
+		echo $? ${PIPESTATUS[1]}\n");
+	assert_eq!(out.stdout, b"0 1 0\n1 0\n");
+}
+
+#[test]
+fn errexit() {
+	let out = run_script("ish-test-errexit", b"set -e\n\
+		if false; then echo no; fi\n\
+		false && echo no\n\
+		! true\n\
+		set -x\n\
+		echo reached\n\
+		false\n\
+		echo unreachable\n");
+	assert_eq!(out.stdout, b"reached\n");
+	assert_eq!(out.stderr, b"+ echo reached\n+ false\n");
+	assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn errexit_in_compound_commands() {
+	let out = run_script("ish-test-errexit-in-compound-commands", b"set -e\n\
+		if { false; true; }; then echo ok; fi\n\
+		{ false; echo x; } || echo rescued\n\
+		{ false; echo y; } && echo and\n\
+		if { false; echo p; } | cat; then echo piped; fi\n\
+		{ false; echo unreachable; }\n");
+	assert_eq!(out.stdout, b"ok\nx\ny\nand\np\npiped\n");
+	assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn trap() {
+	let out = run_script("ish-test-trap", b"trap 'echo exit $?' EXIT\n\
+		trap 'echo err' ERR\n\
+		trap 'echo usr1' USR1\n\
+		false\n\
+		sh -c 'kill -USR1 $PPID'\n\
+		exit 3\n");
+	assert_eq!(out.stdout, b"err\nusr1\nexit 3\n");
+	assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn arithmetic() {
+	let out = run_script("ish-test-arithmetic", b"i=0\n\
+		while (( i < 3 )); do (( i++ )); done\n\
+		let 'j = i * 2 + 1' 'j <<= 1'\n\
+		echo $i $j $(( (1 + 2) * 3 ** 2 )) $(( 9223372036854775807 + 1 )) $(( j > 10 ? 0x10 : 010 ))\n\
+		(( 0 ))\n");
+	assert_eq!(out.stdout, b"3 14 27 -9223372036854775808 16\n");
+	assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn conditional() {
+	let out = run_script("ish-test-conditional", b"[ -d / -a ! -f / ] && echo dir\n\
+		test 3 -lt 10 && [ abc != abd ] && echo cmp\n\
+		x=notes.txt\n\
+		[[ $x == *.txt && ! $x == \"*.txt\" ]] && echo glob\n\
+		[[ $x =~ ^(no+)tes\\.(t.t)$ ]] && echo ${BASH_REMATCH[1]} ${BASH_REMATCH[2]}\n\
+		[ 1 -eq x ]\n");
+	assert_eq!(out.stdout, b"dir\ncmp\nglob\nno txt\n");
+	assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn utility_builtins() {
+	let out = run_script("ish-test-utility-builtins", b"echo -n a; echo -e ' b\\tc\\c'; echo\n\
+		printf '%s=%03d|' x 7 y 42; printf '%5.1f %x %q\\n' 2.25 255 \"a b\"\n\
+		printf 'one two  three\\n:x:\\n' | { read a b; IFS=: read -r p q r; echo \"[$a][$b][$p][$q][$r]\"; }\n\
+		: && true && ! false\n");
+	assert_eq!(out.stdout, &b"a b\tc\nx=007|y=042|  2.2 ff 'a b'\n[one][two  three][][x][]\n"[..]);
+	assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn read_long_timeout() {
+	let out = run_script("ish-test-read-long-timeout", b"read -t inf x < <(echo a); read -t 1e30 y < <(echo b); echo $x $y\n\
+		read -t -1 z < /dev/null; echo $?\n");
+	assert_eq!(out.stdout, b"a b\n2\n");
+	assert_eq!(out.stderr, &b"ish: read: -1: invalid timeout specification\n"[..]);
+}
+
+#[test]
+fn printf_counts_out_of_range() {
+	let out = run_script("ish-test-printf-counts-out-of-range", b"printf '%.99999999999d\\n' 1; echo $?\n\
+		printf '%*d\\n' 99999999999 1; echo $?\n\
+		printf '%*d|\\n' -3 1 2147483648 2\n\
+		printf '%.2147483647f\\n' 1 > /dev/null; echo $?\n");
+	assert_eq!(out.stdout, b"1\n1\n1  |\n1\n");
+	let stderr = String::from_utf8_lossy(&out.stderr);
+	let errors: Vec<&str> = stderr.lines().collect();
+	assert_eq!(&errors[.. 3], &["ish: printf: 99999999999: invalid precision", "ish: printf: 99999999999: invalid field width",
+		"ish: printf: 2147483648: invalid field width"]);
+	// The output is too long for snprintf, which fails with an errno that depends on the C library.
+	assert!(errors[3].starts_with("ish: printf: %.2147483647f: "));
+}
+
+#[test]
+fn directories() {
+	let base = env::temp_dir().join("ish-test-directories.d");
+	let _ = fs::remove_dir_all(&base);
+	fs::create_dir_all(base.join("real/sub")).unwrap();
+	fs::create_dir_all(base.join("cdpath/proj")).unwrap();
+	::std::os::unix::fs::symlink(base.join("real"), base.join("link")).unwrap();
+	let base = base.to_str().unwrap();
+	let script = format!("cd {0}/link/sub && cd .. && pwd\n\
+		cd -P . && pwd\n\
+		cd - && echo $OLDPWD\n\
+		CDPATH={0}/cdpath cd proj\n\
+		cd {0} && pushd real && pushd {0}/cdpath && dirs -l -p\n\
+		popd +1 && popd && pwd\n\
+		cd {0}/missing\n", base);
+	let out = run_script("ish-test-directories", script.as_bytes());
+	assert_eq!(String::from_utf8_lossy(&out.stdout), format!("{0}/link\n{0}/real\n{0}/link\n{0}/real\n{0}/cdpath/proj\n\
+		{0}/real {0}\n{0}/cdpath {0}/real {0}\n{0}/cdpath\n{0}/real\n{0}\n{0}/cdpath {0}\n{0}\n{0}\n", base));
+	assert_eq!(String::from_utf8_lossy(&out.stderr), format!("ish: cd: {}/missing: No such file or directory\n", base));
+	assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn builtin_redirects() {
+	let log = env::temp_dir().join("ish-test-builtin-redirects.log");
+	let log = log.to_str().unwrap();
+	let script = format!("cd / > {0} && pwd\n\
+		printf 'a\\nb\\n' > {0}; while read l; do last=$l; done < {0}; echo $last\n\
+		{{ n=1; echo grouped; }} > {0}; echo $n; cat {0}\n\
+		exec 3> {0}\n\
+		sh -c 'echo fd3 >&3'\n\
+		cat {0}\n", log);
+	let out = run_script("ish-test-builtin-redirects", script.as_bytes());
+	assert_eq!(out.stdout, &b"/\nb\n1\ngrouped\nfd3\n"[..]);
+}
+
+#[test]
+fn exec() {
+	let out = run_script("ish-test-exec", b"trap 'echo exit trap' EXIT\n\
+		trap 'echo usr1' USR1\n\
+		echo $$\n\
+		exec sh -c 'echo $$; kill -USR1 $$; echo not reached'\n\
+		echo not reached\n");
+	let stdout = String::from_utf8(out.stdout).unwrap();
+	let pids: Vec<&str> = stdout.lines().collect();
+	assert_eq!(pids.len(), 2);
+	assert_eq!(pids[0], pids[1]);
+	assert_eq!(out.status.code(), None);
+}
+
+#[test]
+fn field_splitting() {
+	let out = run_script("ish-test-field-splitting", b"set -- 'a b' '' c\n\
+		printf '[%s]' \"$@\" $@ \"$*\"; echo\n\
+		x='  one   two  '; printf '[%s]' $x \"$x\"; echo\n\
+		IFS=:; y='a::b:'; printf '[%s]' $y; unset IFS; echo\n\
+		e=; set --; printf '[%s]' $e \"$@\" \"\"; echo\n\
+		printf '[%s]' $(printf 'p q\\nr\\n\\n') \"$(echo \"in quotes\")\"; echo\n");
+	assert_eq!(String::from_utf8_lossy(&out.stdout), "[a b][][c][a][b][c][a b  c]\n\
+		[one][two][  one   two  ]\n\
+		[a][][b]\n\
+		[]\n\
+		[p][q][r][in quotes]\n");
+}
+
+#[test]
+fn backquote_substitution() {
+	let out = run_script("ish-test-backquote-substitution", b"echo `echo hi`; printf '[%s]' `echo a b` \"`echo a  b`\"; echo\n\
+		echo `echo \\`echo nested\\`` \"`echo \\\"q\\\" '\\$x'`\" x`printf y`z\n\
+		echo `echo unterminated\n");
+	assert_eq!(String::from_utf8_lossy(&out.stdout), "hi\n[a][b][a b]\nnested q $x xyz\n");
+	assert!(String::from_utf8_lossy(&out.stderr).contains("unexpected end of input, expecting closing `"));
+}
+
+#[test]
+fn tilde_and_brace_expansion() {
+	let out = run_script("ish-test-tilde-and-brace-expansion", b"echo a{b,c}d x{a,b{1,2}}y {1..4} {01..10..3} {e..a..2}\n\
+		echo \"{a,b}\" '{1..3}' \\{a,b} {a} {1..b} a{,b}\n\
+		HOME=/home/ish; P=~/a:~/b\n\
+		echo ~ ~/bin \"~\" x~ $P {~,/tmp}/z\n");
+	assert_eq!(String::from_utf8_lossy(&out.stdout), "abd acd xay xb1y xb2y 1 2 3 4 01 04 07 10 e c a\n\
+		{a,b} {1..3} {a,b} {a} {1..b} a ab\n\
+		/home/ish /home/ish/bin ~ x~ /home/ish/a:/home/ish/b /home/ish/z /tmp/z\n");
+}
+
+#[test]
+fn brace_sequence_overflow() {
+	let out = run_script("ish-test-brace-sequence-overflow", b"echo {a..z..9223372036854775807} {z..a..-9223372036854775808}\n\
+		echo {1..5..9223372036854775807} {-1..-9223372036854775808..9223372036854775807} {5..1..-9223372036854775808}\n");
+	assert_eq!(out.stdout, b"a z\n1 -1 -9223372036854775808 5\n");
+	assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn process_substitution() {
+	let out = run_script("ish-test-process-substitution", b"diff <(printf 'a\\nb\\n') <(printf 'a\\nc\\n') >/dev/null; echo $?\n\
+		cat <(echo one) <(echo two)\n\
+		while read line; do echo \"[$line]\"; done < <(printf 'x\\ny\\n')\n\
+		echo out > >(tr a-z A-Z); sleep 1\n");
+	assert_eq!(String::from_utf8_lossy(&out.stdout), "1\none\ntwo\n[x]\n[y]\nOUT\n");
+}
+
+#[test]
+fn continuation_prompt() {
+	let mut child = process::Command::new("target/debug/ish")
+		.stdin(process::Stdio::piped())
+		.stdout(process::Stdio::piped())
+		.spawn().unwrap();
+	child.stdin.take().unwrap().write_all(b"echo 'a\nb'\nif true\nthen echo yes; fi\necho one |\ntr a-z A-Z\necho x \\\ny\nPS2='>> '\n[[ -n x &&\n-z '' ]] && echo cond\n").unwrap();
+	let out = child.wait_with_output().unwrap();
+	assert_eq!(String::from_utf8_lossy(&out.stdout), "ish> > a\nb\nish> > yes\nish> > ONE\nish> > x y\nish> ish> >> cond\nish> ");
+}
+
+#[test]
+fn parse_error_position() {
+	let out = run_script("ish-test-parse-error-position", b"echo one\nwhile true; do\n\techo two; done; done\n");
+	let path = env::temp_dir().join("ish-test-parse-error-position");
+	assert_eq!(out.stdout, b"one\n");
+	assert_eq!(String::from_utf8_lossy(&out.stderr), format!("ish: {}:3: syntax error: unexpected 'done'\n\
+		\techo two; done; done\n\
+		\t                ^^^^\n", path.display()));
+	assert_eq!(out.status.code(), Some(2));
+
+	let out = run_script("ish-test-parse-error-terminators", b"if true; then\n");
+	let path = env::temp_dir().join("ish-test-parse-error-terminators");
+	assert_eq!(String::from_utf8_lossy(&out.stderr), format!("ish: {}:1: syntax error: unexpected end of input, expecting 'elif', 'else' or 'fi'\n\
+		if true; then\n\
+		\x20            ^\n", path.display()));
+}
+
+#[test]
+fn error_reporting() {
+	let out = run_script("ish-test-error-reporting", b"nosuchcmd; echo $?\n\
+		/nonexistent/cmd; echo $?\n\
+		cd /nonexistent; echo $?\n\
+		(( 1 / 0 )); echo $?\n\
+		let 'x = 2 ** -1'; echo $?\n\
+		exec nosuchcmd\n");
+	assert_eq!(out.stdout, b"127\n127\n1\n1\n1\n");
+	assert_eq!(String::from_utf8_lossy(&out.stderr), "ish: nosuchcmd: command not found\n\
+		ish: /nonexistent/cmd: No such file or directory\n\
+		ish: cd: /nonexistent: No such file or directory\n\
+		ish: ((: 1 / 0: division by 0\n\
+		ish: let: x = 2 ** -1: exponent less than 0\n\
+		ish: exec: nosuchcmd: command not found\n");
+	assert_eq!(out.status.code(), Some(127));
+
+	let out = run_script("ish-test-error-reporting-arithmetic", b"echo $((10 % 0))\necho unreachable\n");
+	assert!(out.stdout.is_empty());
+	assert_eq!(out.stderr, &b"ish: 10 % 0: division by 0\n"[..]);
+	assert_eq!(out.status.code(), Some(1));
+
+	let out = run_script("ish-test-error-reporting-exit", b"exit abc\necho unreachable\n");
+	assert!(out.stdout.is_empty());
+	assert_eq!(out.stderr, &b"ish: exit: abc: numeric argument required\n"[..]);
+	assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn redirect_failure() {
+	let out = run_script("ish-test-redirect-failure", b"echo x > /nonexistent/out.txt; echo $?\n\
+		cat < /nonexistent/in.txt; echo $?\n\
+		cd / < /nonexistent/in.txt; echo $? $PWD\n\
+		{ echo not run; } < /nonexistent/in.txt; echo $?\n");
+	let pwd = env::current_dir().unwrap();
+	assert_eq!(String::from_utf8_lossy(&out.stdout), format!("1\n1\n1 {}\n1\n", pwd.display()));
+	assert_eq!(String::from_utf8_lossy(&out.stderr), "ish: /nonexistent/out.txt: No such file or directory\n\
+		ish: /nonexistent/in.txt: No such file or directory\n\
+		ish: /nonexistent/in.txt: No such file or directory\n\
+		ish: /nonexistent/in.txt: No such file or directory\n");
+}
+
+fn builtin_sum(shell: &mut ish::Shell, _: &mut ish::Io, args: &[&[u8]]) -> u8 {
+	let sum: i64 = args.iter().map(|a| str::from_utf8(a).unwrap().parse::<i64>().unwrap()).sum();
+	shell.set_var(b"SUM", ish::Value::Scalar(sum.to_string().into_bytes()));
+	0
+}
+
+#[test]
+fn embedded_shell() {
+	let mut shell = ish::Shell::new();
+	shell.set_var(b"X", ish::Value::Scalar(b"2".to_vec()));
+	shell.register_builtin(b"sum", builtin_sum);
+	assert_eq!(shell.eval_str(b"sum 1 $X 3 && Y=$((SUM * 2))").unwrap(), ish::Outcome::Done(0));
+	assert_eq!(shell.var(b"SUM"), Some(&ish::Value::Scalar(b"6".to_vec())));
+	assert_eq!(shell.var(b"Y"), Some(&ish::Value::Scalar(b"12".to_vec())));
+	assert!(shell.eval_str(b"if true; then").unwrap_err().is_incomplete());
+	assert_eq!(shell.eval_str(b"false").unwrap(), ish::Outcome::Done(1));
+	assert_eq!(shell.last_status(), 1);
+}
+
+#[test]
+fn embedded_shell_exit() {
+	let mut shell = ish::Shell::new();
+	assert_eq!(shell.eval_str(b"trap 'Z=trapped' EXIT; exit 3; Y=unreachable").unwrap(), ish::Outcome::Exit(3));
+	assert_eq!(shell.var(b"Y"), None);
+	assert_eq!(shell.eval_str(b"if true; then echo ${; fi; Y=unreachable").unwrap(), ish::Outcome::Exit(1));
+	assert_eq!(shell.eval_str(b"set -e; while true; do false; done; Y=unreachable").unwrap(), ish::Outcome::Exit(1));
+	assert_eq!(shell.var(b"Y"), None);
+	assert_eq!(shell.eval_str(b"set +e; exit abc").unwrap(), ish::Outcome::Exit(2));
+	assert_eq!(shell.eval_str(b"true").unwrap(), ish::Outcome::Done(0));
+	assert_eq!(shell.var(b"Z"), None);
+	assert_eq!(shell.run_exit_trap(4), 4);
+	assert_eq!(shell.var(b"Z"), Some(&ish::Value::Scalar(b"trapped".to_vec())));
+}
+
+#[test]
+fn embedded_shell_environment() {
+	let mut shell = ish::Shell::new();
+	shell.set_var(b"ISH_TEST_EXPORTED", ish::Value::Scalar(b"1".to_vec()));
+	shell.export_var(b"ISH_TEST_EXPORTED");
+	assert_eq!(shell.eval_str(b"ISH_TEST_LOCAL=2; X=$(printenv ISH_TEST_EXPORTED; printenv ISH_TEST_LOCAL || echo none)").unwrap(), ish::Outcome::Done(0));
+	assert_eq!(shell.var(b"X"), Some(&ish::Value::Scalar(b"1\nnone".to_vec())));
+	assert_eq!(env::var_os("ISH_TEST_EXPORTED"), None);
+	assert_eq!(shell.eval_str(b"PATH=/nonexistent; rehash; ls 2> /dev/null").unwrap(), ish::Outcome::Done(127));
+	assert!(env::var_os("PATH").map_or(false, |path| path != "/nonexistent"));
+}
+
+struct Count;
+
+impl ish::Builtin for Count {
+	fn run(&self, shell: &mut ish::Shell, _: &mut ish::Io, args: &[&[u8]]) -> u8 {
+		shell.set_var(b"COUNT", ish::Value::Scalar(args.len().to_string().into_bytes()));
+		0
+	}
+
+	fn help(&self) -> &str {
+		"count [arguments]\nCounts its arguments."
+	}
+
+	fn completion(&self) -> ish::CompletionHint {
+		ish::CompletionHint::Nothing
+	}
+}
+
+#[test]
+fn builtin_registry() {
+	let mut shell = ish::Shell::new();
+	shell.register_builtin(b"count", Count);
+	shell.register_builtin(b"true", |_: &mut ish::Shell, _: &mut ish::Io, _: &[&[u8]]| 3);
+	assert_eq!(shell.eval_str(b"count a b c").unwrap(), ish::Outcome::Done(0));
+	assert_eq!(shell.var(b"COUNT"), Some(&ish::Value::Scalar(b"3".to_vec())));
+	assert_eq!(shell.eval_str(b"true").unwrap(), ish::Outcome::Done(3));
+	assert_eq!(shell.builtin(b"count").unwrap().help().lines().next(), Some("count [arguments]"));
+	assert_eq!(shell.builtin(b"cd").unwrap().completion(), ish::CompletionHint::Directories);
+	assert!(shell.builtin_names().contains(&&b"help"[..]));
+	shell.unregister_builtin(b"count");
+	assert!(shell.builtin(b"count").is_none());
+
+	let out = run_script("ish-test-builtin-registry", b"
+f=/tmp/ish-test-help-$$
+help cd pwd > $f
+echo status $?
+cat $f
+rm $f
+help | grep -c '^read '
+help nosuch
+echo status $?
+printf '%s\\n' one two | { read a; read b; echo $b $a; }
+");
+	assert_eq!(str::from_utf8(&out.stdout).unwrap(),
+		"status 0\ncd [-L|-P] [directory | -]\nChanges the current directory, searching CDPATH for relative names.\n\
+		 pwd [-L|-P]\nPrints the current directory.\n1\nstatus 1\ntwo one\n");
+	assert_eq!(str::from_utf8(&out.stderr).unwrap(), "ish: help: nosuch: no help topics match\n");
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn dump_ast() {
+	use std::ffi::OsStr;
+	use std::os::unix::ffi::OsStrExt;
+
+	let list = {
+		let source = b"x=1 cmd a 2> err; while false; do :; done".to_vec();
+		ish::ast::parse(&source).unwrap()
+	};
+	assert_eq!(list.items.len(), 2);
+	match list.items[0].first.commands[0] {
+		ish::ast::Command::Simple(ref c) => {
+			assert_eq!(c.name, Some(ish::ast::Word(b"cmd".to_vec())));
+			assert_eq!(c.redirects[0].from, 2);
+		},
+		_ => panic!("not a simple command"),
+	}
+
+	// Words are arrays of bytes, which keeps those that are not UTF-8.
+	let out = process::Command::new("target/debug/ish").args(&[OsStr::new("--dump-ast"), OsStr::new("-c"), OsStr::from_bytes(b"! echo \xff | wc -c > f &")]).output().unwrap();
+	let word = |w: &[u8]| format!("{:?}", w).replace(" ", "");
+	assert_eq!(str::from_utf8(&out.stdout).unwrap(), format!("{{\"items\":[{{\"first\":{{\"commands\":[\
+		{{\"Simple\":{{\"assignments\":[],\"name\":{},\"arguments\":[{}],\"redirects\":[]}}}},\
+		{{\"Simple\":{{\"assignments\":[],\"name\":{},\"arguments\":[{}],\"redirects\":[{{\"target\":{},\"from\":1,\"typ\":\"Output\"}}]}}}}],\
+		\"is_negated\":true,\"text\":{},\"command_texts\":[{},{}]}},\
+		\"rest\":[],\"is_background\":true,\"text\":{}}}]}}\n",
+		word(b"echo"), word(b"\xff"), word(b"wc"), word(b"-c"), word(b"f"),
+		word(b"! echo \xff | wc -c > f"), word(b"echo \xff"), word(b"wc -c > f"), word(b"! echo \xff | wc -c > f")));
+	assert_eq!(out.status.code(), Some(0));
+
+	let out = process::Command::new("target/debug/ish").args(&["--dump-ast", "-c", "echo ;;"]).output().unwrap();
+	assert!(out.stdout.is_empty());
+	assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn eval_owned_ast() {
+	let list = {
+		let source = b"X=$((1 + 2)); if [[ $X == 3 ]]; then Y=yes; fi; (( X > 2 )) && echo ok > /dev/null".to_vec();
+		ish::ast::parse(&source).unwrap()
+	};
+	let mut shell = ish::Shell::new();
+	assert_eq!(shell.eval_ast(&list), ish::Outcome::Done(0));
+	assert_eq!(shell.var(b"Y"), Some(&ish::Value::Scalar(b"yes".to_vec())));
+}
+
+#[test]
+#[cfg(not(feature = "serialize"))]
+fn dump_ast_without_serialize() {
+	let out = process::Command::new("target/debug/ish").args(&["--dump-ast", "-c", "echo"]).output().unwrap();
+	assert!(out.stdout.is_empty());
+	assert_eq!(out.stderr, &b"ish: --dump-ast: ish was built without the serialize feature\n"[..]);
+	assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn job_listing() {
+	let out = run_script("ish-test-job-listing", b"sleep 1 | cat &\n\
+		sh -c 'exit 3' &\n\
+		sleep 0.3\n\
+		jobs\n\
+		jobs -l | sed 's/ *[0-9][0-9]* Running/  PID Running/'\n\
+		jobs\n\
+		diff <(echo a) <(echo a) && jobs -p | wc -l\n");
+	assert_eq!(str::from_utf8(&out.stdout).unwrap(), "\
+		[2]+  Exit 3                  sh -c 'exit 3'\n\
+		[1]+  Running                 sleep 1 | cat\n\
+		[1]+  PID Running                 sleep 1\n  PID Running                 | cat\n\
+		[1]+  Running                 sleep 1 | cat\n\
+		1\n");
+}
+
+#[test]
+fn lost_children() {
+	// With SIGCHLD ignored, children are collected by the kernel and waiting for them fails with ECHILD.
+	let out = run_script("ish-test-lost-children", b"trap '' CHLD\n\
+		sleep 0.1; echo $?\n\
+		sleep 0.1 &\n\
+		sleep 0.3\n\
+		jobs\n\
+		trap - CHLD\n\
+		sh -c 'exit 4'; echo $?\n");
+	assert_eq!(str::from_utf8(&out.stdout).unwrap(), "127\n[1]+  Exit 127                sleep 0.1\n4\n");
+	assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn foreign_children() {
+	// A child of an embedder is neither collected nor mistaken for a job.
+	let mut child = process::Command::new("sh").args(&["-c", "exit 5"]).spawn().unwrap();
+	let mut shell = ish::Shell::new();
+	assert_eq!(shell.eval_str(b"sleep 0.2; sleep 0.1 | false").unwrap(), ish::Outcome::Done(1));
+	assert_eq!(shell.eval_str(b"sleep 0.1 & sleep 0.2; jobs > /dev/null").unwrap(), ish::Outcome::Done(0));
+	assert_eq!(child.wait().unwrap().code(), Some(5));
+}
+
+#[test]
+fn no_job_control() {
+	let out = run_script("ish-test-no-job-control", b"sleep 0.1 &\nfg; echo $?\nbg %1; echo $?\n");
+	assert_eq!(out.stdout, b"1\n1\n");
+	assert_eq!(out.stderr, &b"ish: fg: no job control\nish: bg: no job control\n"[..]);
+}
+
+#[test]
+fn terminal_modes() {
+	let job = env::temp_dir().join("ish-test-terminal-modes-job");
+	let check = env::temp_dir().join("ish-test-terminal-modes-check");
+	fs::File::create(&check).unwrap().write_all(b"if stty -a | grep -qw -- -echo; then echo echo-off; else echo echo-on; fi\n").unwrap();
+	fs::File::create(&job).unwrap().write_all(format!("stty -echo\nkill -TSTP $$\nsh {}\n", check.display()).as_bytes()).unwrap();
+	let mut t = TmuxHandler::new("terminal-modes").unwrap();
+	// The job stops with echo off, the shell gets its own modes back, and `fg` gives the job its modes again.
+	for line in &[format!("sh {}", job.display()), format!("sh {}", check.display()), "fg".to_string(), format!("sh {}", check.display())] {
+		t.input(line.as_bytes());
+		sleep_little();
+	}
+	let screen = t.capture();
+	let modes: Vec<&[u8]> = screen.split(|&c| c == b'\n').filter(|l| l.starts_with(b"echo-")).collect();
+	assert_eq!(modes, vec![&b"echo-on"[..], b"echo-off", b"echo-on"]);
+}
+
+#[test]
+fn terminal_with_redirected_output() {
+	let reader = env::temp_dir().join("ish-test-terminal-reader");
+	let log = env::temp_dir().join("ish-test-terminal-log");
+	fs::File::create(&reader).unwrap().write_all(b"read line\necho got $line\n").unwrap();
+	let mut t = TmuxHandler::new("terminal-with-redirected-output").unwrap();
+	// The inner shell finds the terminal through /dev/tty, so its job can read from it in the foreground.
+	for line in &[format!("target/debug/ish > {}", log.display()), format!("sh {}", reader.display()), "hello".to_string(), "exit".to_string()] {
+		t.input(line.as_bytes());
+		sleep_little();
+	}
+	let mut out = vec![];
+	fs::File::open(&log).unwrap().read_to_end(&mut out).unwrap();
+	assert_eq!(out, b"ish> got hello\nish> ");
+}
+
+#[test]
+fn job_control_only_in_the_foreground() {
+	let script = env::temp_dir().join("ish-test-job-control-script");
+	let groups = env::temp_dir().join("ish-test-job-control-groups");
+	let script_log = env::temp_dir().join("ish-test-job-control-script-log");
+	let background_log = env::temp_dir().join("ish-test-job-control-background-log");
+	fs::File::create(&groups).unwrap().write_all(b"echo $(ps -o pgid= -p $PPID) $(ps -o pgid= -p $$) $(ps -o tpgid= -p $$) > $1\n").unwrap();
+	fs::File::create(&script).unwrap().write_all(format!("sh {} {}\n", groups.display(), script_log.display()).as_bytes()).unwrap();
+	let mut t = TmuxHandler::new("job-control-only-in-the-foreground").unwrap();
+	// A script does not put its commands in process groups of their own, and neither does a background subshell,
+	// which must not take the terminal from the foreground.
+	t.input(format!("target/debug/ish {}", script.display()).as_bytes());
+	sleep_little();
+	t.input(format!("{{ sh {} {}; }} & sleep 1", groups.display(), background_log.display()).as_bytes());
+	sleep_little();
+	sleep_little();
+	sleep_little();
+	let read_groups = |path: &path::Path| -> Vec<String> {
+		let mut out = String::new();
+		fs::File::open(path).unwrap().read_to_string(&mut out).unwrap();
+		out.split_whitespace().map(|s| s.to_string()).collect()
+	};
+	let script_groups = read_groups(&script_log);
+	assert_eq!(script_groups[0], script_groups[1]);
+	let background_groups = read_groups(&background_log);
+	assert_eq!(background_groups[0], background_groups[1]);
+	assert!(background_groups[1] != background_groups[2]);
+}
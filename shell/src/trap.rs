@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use libc;
+use nix;
+use nix::sys::signal::*;
+
+/// Signals that arrived but whose traps have not run yet, as a bit set.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+/// Signals whose disposition is `record_signal`, as a bit set.
+static CAUGHT: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn record_signal(sig: libc::c_int) {
+	PENDING.fetch_or(1 << sig, Ordering::SeqCst);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Condition {
+	Exit,
+	Err,
+	Signal(i32),
+}
+
+pub fn signal_name(sig: Signal) -> &'static str {
+	match sig {
+		Signal::SIGHUP => "HUP",
+		Signal::SIGINT => "INT",
+		Signal::SIGQUIT => "QUIT",
+		Signal::SIGILL => "ILL",
+		Signal::SIGTRAP => "TRAP",
+		Signal::SIGABRT => "ABRT",
+		Signal::SIGBUS => "BUS",
+		Signal::SIGFPE => "FPE",
+		Signal::SIGKILL => "KILL",
+		Signal::SIGUSR1 => "USR1",
+		Signal::SIGSEGV => "SEGV",
+		Signal::SIGUSR2 => "USR2",
+		Signal::SIGPIPE => "PIPE",
+		Signal::SIGALRM => "ALRM",
+		Signal::SIGTERM => "TERM",
+		Signal::SIGCHLD => "CHLD",
+		Signal::SIGCONT => "CONT",
+		Signal::SIGSTOP => "STOP",
+		Signal::SIGTSTP => "TSTP",
+		Signal::SIGTTIN => "TTIN",
+		Signal::SIGTTOU => "TTOU",
+		Signal::SIGURG => "URG",
+		Signal::SIGXCPU => "XCPU",
+		Signal::SIGXFSZ => "XFSZ",
+		Signal::SIGVTALRM => "VTALRM",
+		Signal::SIGPROF => "PROF",
+		Signal::SIGWINCH => "WINCH",
+		Signal::SIGIO => "IO",
+		Signal::SIGSYS => "SYS",
+		_ => "UNKNOWN",
+	}
+}
+
+impl Condition {
+	/// Accepts `EXIT`, `ERR`, signal names with or without the `SIG` prefix, and signal numbers.
+	pub fn parse(s: &[u8]) -> Option<Condition> {
+		let s = String::from_utf8_lossy(s).to_uppercase();
+		match &s[..] {
+			"EXIT" | "0" => { return Some(Condition::Exit); },
+			"ERR" => { return Some(Condition::Err); },
+			_ => {},
+		}
+		if let Ok(n) = s.parse() {
+			return Signal::from_c_int(n).ok().map(|sig| Condition::Signal(sig as i32));
+		}
+		let name = if s.starts_with("SIG") { &s[3 ..] } else { &s[..] };
+		Signal::iterator().find(|&sig| signal_name(sig) == name).map(|sig| Condition::Signal(sig as i32))
+	}
+
+	pub fn name(&self) -> String {
+		match *self {
+			Condition::Exit => "EXIT".to_string(),
+			Condition::Err => "ERR".to_string(),
+			Condition::Signal(n) => format!("SIG{}", Signal::from_c_int(n).map_or("UNKNOWN", signal_name)),
+		}
+	}
+}
+
+/// The trap actions of the shell. An empty action means that the condition is ignored.
+#[derive(Debug)]
+pub struct Traps {
+	actions: BTreeMap<Condition, Vec<u8>>,
+	pub in_err_trap: bool,
+}
+
+fn set_disposition(sig: Signal, handler: SigHandler) -> nix::Result<()> {
+	let flags = if handler == SigHandler::SigDfl || handler == SigHandler::SigIgn { SaFlags::empty() } else { SA_RESTART };
+	let ref sa = SigAction::new(handler, flags, SigSet::empty());
+	unsafe{ sigaction(sig, sa) }.map(|_| ())
+}
+
+impl Traps {
+	pub fn new() -> Traps {
+		Traps { actions: BTreeMap::new(), in_err_trap: false }
+	}
+
+	pub fn get(&self, cond: Condition) -> Option<&Vec<u8>> {
+		self.actions.get(&cond)
+	}
+
+	pub fn iter(&self) -> ::std::collections::btree_map::Iter<Condition, Vec<u8>> {
+		self.actions.iter()
+	}
+
+	/// Sets the action for a condition, or restores the default when `action` is `None`.
+	pub fn set(&mut self, cond: Condition, action: Option<Vec<u8>>) -> nix::Result<()> {
+		if let Condition::Signal(n) = cond {
+			let sig = Signal::from_c_int(n)?;
+			if sig == Signal::SIGKILL || sig == Signal::SIGSTOP {
+				return Err(nix::Error::invalid_argument());
+			}
+			let handler = match action {
+				None => SigHandler::SigDfl,
+				Some(ref a) if a.is_empty() => SigHandler::SigIgn,
+				Some(_) => SigHandler::Handler(record_signal),
+			};
+			set_disposition(sig, handler)?;
+			if handler == SigHandler::Handler(record_signal) {
+				CAUGHT.fetch_or(1 << n, Ordering::SeqCst);
+			} else {
+				CAUGHT.fetch_and(!(1 << n), Ordering::SeqCst);
+			}
+		}
+		match action {
+			Some(a) => { self.actions.insert(cond, a); },
+			None => { self.actions.remove(&cond); },
+		}
+		Ok(())
+	}
+
+	/// Installs the dispositions of the traps again after `reset_dispositions`.
+	pub fn reinstall(&mut self) {
+		let actions: Vec<(Condition, Vec<u8>)> = self.actions.iter().map(|(&c, a)| (c, a.clone())).collect();
+		for (cond, action) in actions {
+			let _ = self.set(cond, Some(action));
+		}
+	}
+
+	/// Drops the traps that a subshell does not inherit. Ignored conditions stay ignored.
+	pub fn reset_for_subshell(&mut self) {
+		let actions = ::std::mem::replace(&mut self.actions, BTreeMap::new());
+		self.actions = actions.into_iter().filter(|&(_, ref a)| a.is_empty()).collect();
+		self.in_err_trap = false;
+	}
+}
+
+/// Restores the default disposition of caught signals in a forked child.
+pub fn reset_dispositions() {
+	let caught = CAUGHT.swap(0, Ordering::SeqCst);
+	PENDING.store(0, Ordering::SeqCst);
+	for sig in Signal::iterator() {
+		if caught & (1 << sig as i32) != 0 {
+			let _ = set_disposition(sig, SigHandler::SigDfl);
+		}
+	}
+}
+
+/// Returns the signals received since the last call, in the order of their numbers.
+pub fn take_pending() -> Vec<Condition> {
+	let pending = PENDING.swap(0, Ordering::SeqCst);
+	Signal::iterator()
+		.filter(|&sig| pending & (1 << sig as i32) != 0)
+		.map(|sig| Condition::Signal(sig as i32))
+		.collect()
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Value {
+	Scalar(Vec<u8>),
+	Array(Vec<Vec<u8>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+	pub value: Value,
+	pub exported: bool,
+}
+
+pub struct Variables {
+	imp: HashMap<Vec<u8>, Variable>,
+}
+
+impl Variables {
+	pub fn new() -> Variables {
+		use std::os::unix::ffi::OsStringExt;
+		let mut imp = HashMap::new();
+		for (k, v) in env::vars_os() {
+			imp.insert(k.into_vec(), Variable { value: Value::Scalar(v.into_vec()), exported: true });
+		}
+		Variables { imp: imp }
+	}
+
+	pub fn get(&self, name: &[u8]) -> Option<&Value> {
+		self.imp.get(name).map(|v| &v.value)
+	}
+
+	/// The value of a variable where a string is expected, which is the first element of an array.
+	pub fn get_str(&self, name: &[u8]) -> Option<&[u8]> {
+		self.imp.get(name).map(|v| match v.value {
+			Value::Scalar(ref s) => &s[..],
+			Value::Array(ref a) => a.get(0).map_or(&[][..], |s| &s[..]),
+		})
+	}
+
+	/// The `name=value` entries of the exported variables, which are the environment of commands.
+	/// The process environment is left alone, so a program that embeds the shell keeps its own.
+	pub fn environment(&self) -> Vec<Vec<u8>> {
+		self.imp.iter().filter(|&(_, var)| var.exported).map(|(name, _)| {
+			[&name[..], b"=", self.get_str(name).unwrap()].concat()
+		}).collect()
+	}
+
+	pub fn set(&mut self, name: &[u8], value: Value) {
+		self.imp.entry(name.to_vec()).or_insert(Variable { value: Value::Scalar(vec![]), exported: false }).value = value;
+	}
+
+	pub fn set_scalar(&mut self, name: &[u8], value: &[u8]) {
+		self.set(name, Value::Scalar(value.to_vec()));
+	}
+
+	pub fn export(&mut self, name: &[u8]) {
+		self.imp.entry(name.to_vec()).or_insert(Variable { value: Value::Scalar(vec![]), exported: false }).exported = true;
+	}
+
+	pub fn unset(&mut self, name: &[u8]) {
+		self.imp.remove(name);
+	}
+
+	/// Takes a copy of a variable so that a temporary assignment can be undone with `restore`.
+	pub fn save(&self, name: &[u8]) -> Option<Variable> {
+		self.imp.get(name).cloned()
+	}
+
+	pub fn restore(&mut self, name: &[u8], saved: Option<Variable>) {
+		self.unset(name);
+		if let Some(var) = saved {
+			self.imp.insert(name.to_vec(), var);
+		}
+	}
+}